@@ -1,26 +1,634 @@
-use shared::Packet;
-use std::collections::VecDeque;
-use std::{cell::RefCell, collections::HashMap, f64::consts::PI, rc::Rc};
-use wasm_bindgen::{JsCast, prelude::*};
+use serde::{Deserialize, Serialize};
+use shared::{
+    CloseReason, Kind, Lines, Mark, Msg, Packet, PeerState, Stamped, Tunables, Welcome,
+    cursor_owner, is_hex_color,
+};
+use std::collections::{HashSet, VecDeque};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    f64::consts::PI,
+    rc::Rc,
+};
+use wasm_bindgen::{JsCast, convert::FromWasmAbi, prelude::*};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::MediaQueryListEvent;
+#[cfg(feature = "audio")]
+use web_sys::{
+    AudioContext, AudioNode, BiquadFilterNode, BiquadFilterType, GainNode, OscillatorNode,
+    OscillatorType,
+};
 use web_sys::{
-    AudioContext, CanvasRenderingContext2d, GainNode, HtmlCanvasElement, OscillatorNode,
-    PointerEvent, WebSocket, js_sys::Math,
+    Blob, BlobPropertyBag, CanvasRenderingContext2d, CloseEvent, DragEvent, FileReader, Gamepad,
+    HtmlAnchorElement, HtmlCanvasElement, HtmlElement, HtmlInputElement, KeyboardEvent,
+    MessageEvent, MouseEvent, PointerEvent, ReadableStream, ReadableStreamDefaultReader,
+    ReadableStreamReadResult, Response, Url, WebSocket, WritableStream,
+    WritableStreamDefaultWriter, console,
+    js_sys::{self, Function, Math},
 };
-use web_sys::{BiquadFilterNode, BiquadFilterType, MessageEvent, OscillatorType};
 
-// Constants - Movement
-const SPEED_FACTOR: f64 = 0.01;
+// Constants - Movement (operator tunables live in `shared::Tunables`)
 const STOP_RADIUS: f64 = 0.0;
-const TRAIL_LIFE: f64 = 1_000.0;
+const CURSOR_RADIUS: f64 = 20.0; // at size 1, see `Config::cursor_radius`
+const MIN_SIZE: f64 = 0.25; // cursor size preference bounds
+const MAX_SIZE: f64 = 4.0;
+const SIZE_STEP: f64 = 1.25; // per - / = press
+const PAD_DEADZONE: f64 = 0.15; // stick travel ignored around centre, against drift
+const IDLE_PULL_EASE: f64 = 0.0005; // fraction of the way home closed per ms once idle
+const RAINBOW_PERIOD: f64 = 6_000.0; // ms per trip around the hue wheel
+const MAGNET_RADIUS: f64 = 150.0; // px, default reach of `Config::magnet`
+const SAVER_AFTER: f64 = 60_000.0; // ms without input before `Config::screensaver` takes over
+const SAVER_PEERS: usize = 2; // at most this many other cursors about, or it stays off
+const SAVER_PERIOD: (f64, f64) = (29_000.0, 19_000.0); // ms per sweep across, and down
+const SAVER_REACH: f64 = 0.35; // share of the view the path swings either side of centre
+const SAVER_EASE: f64 = 0.002; // fraction of the way onto the path closed per ms
+const SAVER_VOLUME: f64 = 0.3; // share of `Config::volume` played meanwhile
+
+// Constants - Rendering
+const DIRTY_PAD: f64 = 2.0; // slack around dirty boxes for anti-aliased edges
+const CULL_MARGIN: f64 = 64.0; // px past the view edge a peer still gets drawn
+const GRID_CELL: f64 = 64.0; // px, side of a `Grid` bucket
+const FRAME_BUDGET: f64 = 16.0; // ms a frame may take to draw, default for `Config::frame_budget`
+const FRAME_SMOOTH: f64 = 0.1; // weight of the newest frame in the average draw time
+const QUALITY_HOLD: f64 = 2_000.0; // ms to stay at a quality level before changing again
+
+// Constants - Connection
+const WS_URL: &str = "wss://femtanyl-systems.fly.dev/ws";
+const CONFIG_URL: &str = "https://femtanyl-systems.fly.dev/config";
+const RECONNECT_MIN: i32 = 1_000; // ms
+const RECONNECT_MAX: i32 = 30_000;
+const PING_INTERVAL: f64 = 1_000.0; // ms between round-trip probes
+const STALL_AFTER: f64 = 40_000.0; // ms without a word from the server (it ticks every 15 s) before reconnecting
+const RECONCILE_SLACK: f64 = 8.0; // px an echoed move may be off what we sent (quantization)
+const UNACKED_MAX: usize = 64; // sent moves remembered while waiting for their echo
+const NOTICE_LIFE: f64 = 4_000.0; // ms a server notice stays on screen
+const FRAME_SLACK: f64 = 2.0; // ms early a capped frame may run, absorbing vsync jitter
+
+// Constants - Overlay
+const STATUS_HEIGHT: f64 = 28.0;
+const COLOR_KEY: &str = "femtanyl.color"; // localStorage key for the picked color
+const SIZE_KEY: &str = "femtanyl.size"; // localStorage key for the cursor size
+const SESSION_KEY: &str = "femtanyl.session"; // localStorage key for `Session`
+const SESSION_SAVE: f64 = 1_000.0; // ms between session writes
+const SESSION_TTL: f64 = 600_000.0; // ms a saved session is picked up again within
+const RTT_SAMPLES: usize = 30; // one per ping: ~30 s of history
+const SPARK_W: f64 = 120.0;
+const BYTE_WINDOW: f64 = 5_000.0; // ms the debug overlay's byte rates average over
+const SPARK_H: f64 = 32.0;
+
+// Constants - Camera
+const FOLLOW_EASE: f64 = 0.005; // fraction of the remaining pan closed per ms
+
+// Constants - Spotlight
+const SPOT_WINDOW: f64 = 1_000.0; // ms of movement that counts toward leading
+const SPOT_MIN: f64 = 5.0; // px of recent movement needed to lead at all
+const SPOT_DIM: f64 = 0.25; // alpha for everyone but the leader
+const SPOT_EASE: f64 = 0.004; // fraction of the glow change closed per ms
+const GLOW_BLUR: f64 = 24.0;
+
+// Constants - Marks
+const MARK_LIFE: f64 = 1_000.0; // ms a click marker stays up
+const MARK_RADIUS: f64 = 40.0; // px a marker's ring grows to
+const MARK_SLOP: f64 = 5.0; // px the pointer may travel and still count as a click
+const MARK_WAIT: f64 = 300.0; // ms a click waits to be sure it isn't half a double-click
+#[cfg(feature = "audio")]
+const MARK_CHIRP: f64 = 0.15; // s of blip when one lands
+const SHAKE_PX: f64 = 4.0; // strongest camera shake, for a marker dead centre
+const SHAKE_DECAY: f64 = 120.0; // ms time constant the shake dies down with
+const SHAKE_HZ: f64 = 0.03; // wobbles per ms
+
+// Constants - Rewind
+const REWIND_SPAN: f64 = 5_000.0; // ms of my own movement R plays back
+const REWIND_ALPHA: f64 = 0.5; // how solid the played-back ghost is
+
+// Constants - Vector export (V)
+const PATH_MAX: usize = 20_000; // points of my own path kept, oldest dropped first
+const PATH_STEP: f64 = 1.0; // px the cursor must move before another point is kept
+const PATH_TOLERANCE: f64 = 1.5; // px the exported path may stray from the recorded one
+
+// Constants - Audio (the graph itself is behind the `audio` feature)
+#[cfg(feature = "audio")]
+const MAX_VOLUME: f64 = 2.0; // hard ceiling, whatever the served volume says
+const AUDIO_INTERVAL: f64 = 1_000.0 / 60.0; // ms between audio param updates
+#[cfg(feature = "audio")]
+const AUDIO_ATTACK: f64 = 0.05; // s to swell when movement picks up
+#[cfg(feature = "audio")]
+const AUDIO_RELEASE: f64 = 0.2; // s to fade when it settles
+const AUDIO_SMOOTH: f64 = 80.0; // ms, default for `Config::audio_smooth`
+#[cfg(feature = "audio")]
+const REVERB_SECS: f32 = 1.5; // length of the generated room's echo
+#[cfg(feature = "audio")]
+const REVERB_DECAY: f64 = 3.0; // how steeply it dies away, higher is a smaller room
+
+// Constants - Connection quality (peers whose moves arrive unevenly look washed out)
+const JITTER_WEIGHT: f64 = 0.1; // weight of the newest gap in a peer's arrival stats
+const JITTER_PAUSE: f64 = 1_000.0; // ms between moves taken as the peer resting, not lag
+const JITTER_BAD: f64 = 60.0; // ms of spread in the gaps at which the effect is strongest
+const JITTER_GREY: f64 = 0.6; // most a laggy peer's color is washed out
+
+// Constants - Reduced motion (`prefers-reduced-motion`)
+const CALM_TRAIL: f64 = 200.0; // ms, longest trail drawn
+const CALM_VOLUME: f64 = 0.3; // share of the usual volume
+
+/// Tunables: the server's `/config`, then the page url on top.
+struct Config {
+    /// bits per axis for sent positions (?precision=12), full f32 when unset
+    precision: Option<u8>,
+    /// kinds to subscribe to (?kinds=move,chat), everything when unset
+    kinds: Option<Vec<Kind>>,
+    /// clear only last frame's drawn boxes instead of the whole canvas (?clear=dirty)
+    dirty_clear: bool,
+    /// ms trail points live (?trail=1000)
+    trail_life: f64,
+    /// fraction of the remaining distance cursors cover per ms (?speed=0.01)
+    speed_factor: f64,
+    /// keyboard cursor speed in px/ms (?keyspeed=0.5)
+    key_speed: f64,
+    /// gamepad cursor speed at full stick in px/ms (?padspeed=0.8)
+    pad_speed: f64,
+    /// loudest the movement sound gets (?volume=0.1)
+    volume: f64,
+    /// dark or light (?theme=light), toggled with T; guessed from ?bg= when only that's given
+    theme: Theme,
+    /// canvas color (?bg=%23fafafa), the theme's when unset
+    background: String,
+    /// how generated colors look (?colors=pastel|vivid|grey|safe)
+    color_style: ColorStyle,
+    /// how trails fade out (?easing=linear|ease-out|exp)
+    easing: Easing,
+    /// drift home to the centre after this many ms without input (?idle=30, in s)
+    idle_pull: Option<f64>,
+    /// render peers this many ms in the past from buffered positions (?delay=100), 0 = off
+    interp_delay: f64,
+    /// cycle my hue over time (?rainbow=1)
+    rainbow: bool,
+    /// cursor and trail radius in CSS px: `CURSOR_RADIUS` times the size
+    /// preference (?size=1.5, or - and = to adjust; remembered either way)
+    cursor_radius: f64,
+    /// ms time constant peers' sound follows their on-screen speed with (?audiosmooth=80), 0 = raw
+    audio_smooth: f64,
+    /// neon glow around each cursor, as px of blur (?bloom=16), 0 = off; shed
+    /// first when frames run slow, since shadows are costly
+    bloom: f64,
+    /// glow the trails too (?bloomtrails=1), costlier still
+    bloom_trails: bool,
+    /// draw only trails, no cursors, for light streaks (?ghost=1, or G)
+    ghost: bool,
+    /// steady jittery pointers with a one-euro filter, as (min cutoff Hz, beta)
+    /// (?smooth=1, tuned with &mincutoff=1&beta=0.01); adds a little lag, so off by default
+    smooth: Option<(f64, f64)>,
+    /// share of the sound sent through a generated room, 0..=1 (?reverb=0.3), 0 = dry and no cost
+    #[cfg(feature = "audio")]
+    reverb: f64,
+    /// nudge the view when a marker lands near the middle (?shake=0 to turn off)
+    shake: bool,
+    /// pull toward nearby peers, as (px/ms at point blank, reach in px); a negative
+    /// strength pushes away instead (?magnet=0.05, &magnetradius=150). off when unset
+    magnet: Option<(f64, f64)>,
+    /// for always-on displays (?screensaver=1): after a minute without input, with
+    /// hardly anyone else on, my cursor drifts a slow loop on its own, quietly
+    screensaver: bool,
+    /// derive random picks (hue, waveform) from this, for reproducible demos (?seed=42)
+    seed: Option<u64>,
+    /// skip drawing peers whose cursor is out of view (?cull=0 to draw everyone)
+    cull: bool,
+    /// draw trails for only this many peers, the busiest (?trails=20); all when unset
+    max_trails: Option<usize>,
+    /// ms a frame may take to draw before detail is shed to keep up (?budget=16), 0 = never
+    frame_budget: f64,
+    /// ms between rendered frames, to save battery (?fps=30), 0 = every animation frame
+    frame_every: f64,
+    /// what reduced motion stood in for, while the OS asks for it (see `reduce_motion`)
+    calm: Option<Motion>,
+    /// room to join, and the settings to create it with if it's new
+    /// (?room=jam&max_peers=8&tick=30&persist=0), passed through to the server
+    room: Vec<(&'static str, String)>,
+    /// WebTransport endpoint to try before the websocket, when the server
+    /// offers one (?wt=0 to stick to the websocket)
+    webtransport: Option<String>,
+}
+
+impl Config {
+    fn from_query(win: &web_sys::Window, served: Tunables) -> Self {
+        let num = |name, default| {
+            query_param(win, name)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        };
+        let bg = query_param(win, "bg");
+        let theme = query_param(win, "theme")
+            .and_then(|t| Theme::parse(&t))
+            .or_else(|| bg.as_deref().and_then(is_light).map(Theme::for_background))
+            .unwrap_or(Theme::Dark);
+        let background = bg.unwrap_or_else(|| theme.background().to_string());
+        Self {
+            precision: query_param(win, "precision").and_then(|p| p.parse().ok()),
+            kinds: query_param(win, "kinds").map(|kinds| {
+                kinds
+                    .split(',')
+                    .filter_map(|k| serde_json::from_value(k.trim().into()).ok())
+                    .collect()
+            }),
+            dirty_clear: query_param(win, "clear").as_deref() == Some("dirty"),
+            trail_life: num("trail", served.trail_life),
+            speed_factor: num("speed", served.speed_factor),
+            key_speed: num("keyspeed", served.key_speed),
+            pad_speed: num("padspeed", served.pad_speed),
+            volume: num("volume", served.volume),
+            theme,
+            background,
+            color_style: query_param(win, "colors")
+                .and_then(|c| ColorStyle::parse(&c))
+                .unwrap_or(ColorStyle::Pastel),
+            easing: query_param(win, "easing")
+                .and_then(|e| Easing::parse(&e))
+                .unwrap_or(Easing::Linear),
+            idle_pull: query_param(win, "idle")
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|s| *s >= 0.0)
+                .map(|s| s * 1_000.0),
+            interp_delay: query_param(win, "delay")
+                .and_then(|s| s.parse::<f64>().ok())
+                .unwrap_or(0.0)
+                .max(0.0),
+            rainbow: query_param(win, "rainbow").is_some_and(|r| r != "0"),
+            cursor_radius: CURSOR_RADIUS * cursor_size(win),
+            audio_smooth: num("audiosmooth", AUDIO_SMOOTH).max(0.0),
+            #[cfg(feature = "audio")]
+            reverb: num("reverb", 0.0).clamp(0.0, 1.0),
+            smooth: query_param(win, "smooth")
+                .is_some_and(|s| s != "0")
+                .then(|| (num("mincutoff", 1.0), num("beta", 0.01))),
+            bloom: num("bloom", 0.0).max(0.0),
+            bloom_trails: query_param(win, "bloomtrails").is_some_and(|b| b != "0"),
+            ghost: query_param(win, "ghost").is_some_and(|g| g != "0"),
+            shake: query_param(win, "shake").is_none_or(|s| s != "0"),
+            magnet: query_param(win, "magnet")
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|s| *s != 0.0)
+                .map(|s| (s, num("magnetradius", MAGNET_RADIUS).max(0.0))),
+            screensaver: query_param(win, "screensaver").is_some_and(|s| s != "0"),
+            seed: query_param(win, "seed").and_then(|s| s.parse().ok()),
+            cull: query_param(win, "cull").is_none_or(|c| c != "0"),
+            max_trails: query_param(win, "trails").and_then(|n| n.parse().ok()),
+            frame_budget: num("budget", FRAME_BUDGET).max(0.0),
+            frame_every: query_param(win, "fps")
+                .and_then(|s| s.parse::<f64>().ok())
+                .filter(|fps| *fps > 0.0)
+                .map_or(0.0, |fps| 1_000.0 / fps),
+            calm: None,
+            room: ["room", "max_peers", "tick", "persist"]
+                .into_iter()
+                .filter_map(|k| Some((k, query_param(win, k)?)))
+                .collect(),
+            webtransport: served
+                .webtransport
+                .filter(|_| query_param(win, "wt").is_none_or(|w| w != "0")),
+        }
+    }
+}
+
+/// The settings reduced motion overrides, kept to put back afterwards.
+#[derive(Clone, Copy)]
+struct Motion {
+    trail_life: f64,
+    volume: f64,
+    shake: bool,
+    interp_delay: f64,
+}
+
+impl Config {
+    /// Follow `prefers-reduced-motion`: short trails, peers snapped to where
+    /// they are rather than glided there, no shake, and quieter sound.
+    fn reduce_motion(&mut self, on: bool) {
+        match (on, self.calm) {
+            (true, None) => {
+                self.calm = Some(Motion {
+                    trail_life: self.trail_life,
+                    volume: self.volume,
+                    shake: self.shake,
+                    interp_delay: self.interp_delay,
+                });
+                self.trail_life = self.trail_life.min(CALM_TRAIL);
+                self.volume *= CALM_VOLUME;
+                self.shake = false;
+                self.interp_delay = 0.0;
+            }
+            (false, Some(m)) => {
+                self.trail_life = m.trail_life;
+                self.volume = m.volume;
+                self.shake = m.shake;
+                self.interp_delay = m.interp_delay;
+                self.calm = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Palette the board is drawn in. Light backgrounds get darker generated
+/// cursor colors and overlay text, so both still stand out.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Theme {
+    Dark,
+    Light,
+}
+
+impl Theme {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Theme::Dark),
+            "light" => Some(Theme::Light),
+            _ => None,
+        }
+    }
+
+    fn for_background(light: bool) -> Self {
+        if light { Theme::Light } else { Theme::Dark }
+    }
+
+    fn toggled(self) -> Self {
+        match self {
+            Theme::Dark => Theme::Light,
+            Theme::Light => Theme::Dark,
+        }
+    }
+
+    fn background(self) -> &'static str {
+        match self {
+            Theme::Dark => "#121212",
+            Theme::Light => "#f4f4f4",
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            Theme::Dark => "#888",
+            Theme::Light => "#555",
+        }
+    }
+
+    /// Lightness of generated cursor colors.
+    fn lightness(self) -> f64 {
+        match self {
+            Theme::Dark => 0.7,
+            Theme::Light => 0.45,
+        }
+    }
+}
+
+/// Saturation and lightness generated colors are built with; only the hue varies.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ColorStyle {
+    Pastel,
+    Vivid,
+    /// no saturation; the hue picks the shade instead
+    Grey,
+    /// Okabe-Ito's colorblind-safe set; the hue picks one
+    Safe,
+}
+
+impl ColorStyle {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "pastel" => Some(ColorStyle::Pastel),
+            "vivid" => Some(ColorStyle::Vivid),
+            "grey" | "gray" => Some(ColorStyle::Grey),
+            "safe" | "okabe-ito" => Some(ColorStyle::Safe),
+            _ => None,
+        }
+    }
+
+    /// Okabe & Ito's palette, told apart under the common color blindnesses.
+    /// Yellow washes out on light backgrounds and black vanishes on dark.
+    const OKABE_ITO: [&str; 8] = [
+        "#e69f00", "#56b4e9", "#009e73", "#0072b2", "#d55e00", "#cc79a7", "#f0e442", "#000000",
+    ];
+
+    /// `#rrggbb` for `hue` (degrees) in this style, readable on `theme`.
+    fn color(self, hue: f64, theme: Theme) -> String {
+        let light = theme == Theme::Light;
+        if self == ColorStyle::Safe {
+            let palette = match light {
+                true => [&Self::OKABE_ITO[..6], &Self::OKABE_ITO[7..]].concat(),
+                false => Self::OKABE_ITO[..7].to_vec(),
+            };
+            let i = (hue.rem_euclid(360.0) / 360.0 * palette.len() as f64) as usize;
+            return palette[i.min(palette.len() - 1)].to_string();
+        }
+        let (s, l) = match self {
+            ColorStyle::Pastel => (0.7, theme.lightness()),
+            ColorStyle::Vivid => (1.0, if light { 0.4 } else { 0.5 }),
+            ColorStyle::Grey => {
+                let shade = hue.rem_euclid(360.0) / 360.0 * 0.4;
+                (0.0, if light { 0.15 } else { 0.5 } + shade)
+            }
+            ColorStyle::Safe => unreachable!("picked from the palette above"),
+        };
+        hsl_hex(hue, s, l)
+    }
+}
+
+/// Whether a `#rgb` / `#rrggbb` color is closer to white than black. `None` for other syntaxes.
+fn is_light(color: &str) -> Option<bool> {
+    let (r, g, b) = rgb(color)?;
+    Some(luma(r, g, b) > 127.5)
+}
+
+/// Rec. 601 luma, 0..=255.
+fn luma(r: f64, g: f64, b: f64) -> f64 {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Channels (0..=255) of a `#rgb` / `#rrggbb` color.
+fn rgb(color: &str) -> Option<(f64, f64, f64)> {
+    let hex = color.strip_prefix('#').filter(|h| h.is_ascii())?;
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok().map(f64::from);
+    let (r, g, b) = match hex.len() {
+        3 => {
+            let c = |i: usize| channel(&hex[i..=i].repeat(2));
+            (c(0)?, c(1)?, c(2)?)
+        }
+        6 => (
+            channel(&hex[0..2])?,
+            channel(&hex[2..4])?,
+            channel(&hex[4..6])?,
+        ),
+        _ => return None,
+    };
+    Some((r, g, b))
+}
+
+/// `color` washed out toward grey by `k` (0..=1), as `#rrggbb`. Other
+/// syntaxes are left alone.
+fn desaturate(color: &str, k: f64) -> String {
+    let Some((r, g, b)) = rgb(color) else {
+        return color.to_string();
+    };
+    let grey = luma(r, g, b);
+    let mix = |c: f64| (c + (grey - c) * k.clamp(0.0, 1.0)).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", mix(r), mix(g), mix(b))
+}
+
+/// How much detail the board is drawn with, highest first.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Quality {
+    High,
+    /// trails half as long, no spotlight glow or bloom
+    Medium,
+    /// trails as one thin line instead of a dot per point
+    Low,
+}
+
+impl Quality {
+    fn label(self) -> &'static str {
+        match self {
+            Quality::High => "high",
+            Quality::Medium => "medium",
+            Quality::Low => "low",
+        }
+    }
+
+    fn lower(self) -> Self {
+        match self {
+            Quality::High => Quality::Medium,
+            _ => Quality::Low,
+        }
+    }
+
+    fn higher(self) -> Self {
+        match self {
+            Quality::Low => Quality::Medium,
+            _ => Quality::High,
+        }
+    }
+
+    /// Halo strength for a head (see `draw_head`): the spotlight's, or the
+    /// bloom's if that's stronger, and none at all below `High`.
+    fn glow(self, spotlight: f64, bloom: f64) -> f64 {
+        match self {
+            Quality::High => spotlight.max(bloom / GLOW_BLUR),
+            _ => 0.0,
+        }
+    }
+
+    /// Fraction of a trail's life that still gets drawn.
+    fn trail_share(self) -> f64 {
+        match self {
+            Quality::High => 1.0,
+            _ => 0.5,
+        }
+    }
+}
+
+/// Rolling draw time, and the quality it has settled on. Steps down a
+/// level while frames run over budget and back up once they're well under,
+/// holding each level a while so it doesn't flicker between them.
+struct FrameBudget {
+    avg: f64,   // ms, smoothed
+    since: f64, // when the quality last changed
+    quality: Quality,
+}
+
+impl FrameBudget {
+    fn new() -> Self {
+        Self {
+            avg: 0.0,
+            since: f64::NEG_INFINITY,
+            quality: Quality::High,
+        }
+    }
+
+    /// Count a frame that took `took` ms to draw at `now`.
+    fn record(&mut self, took: f64, now: f64, budget: f64) {
+        self.avg += (took - self.avg) * FRAME_SMOOTH;
+        if budget <= 0.0 || now - self.since < QUALITY_HOLD {
+            return;
+        }
+        let next = if self.avg > budget {
+            self.quality.lower()
+        } else if self.avg < budget * 0.5 {
+            self.quality.higher()
+        } else {
+            self.quality
+        };
+        if next != self.quality {
+            self.quality = next;
+            self.since = now;
+        }
+    }
+}
+
+/// Fade curve for trail points, applied to both alpha and radius.
+#[derive(Clone, Copy)]
+enum Easing {
+    Linear,
+    /// quadratic: lingers bright, then vanishes quickly
+    EaseOut,
+    /// drops off fast, leaving a long faint tail
+    Exponential,
+}
+
+impl Easing {
+    const EXP_RATE: f64 = 5.0;
 
-// Constants - Audio
-const MAX_VOLUME: f64 = 2.0;
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "ease-out" => Some(Easing::EaseOut),
+            "exp" => Some(Easing::Exponential),
+            _ => None,
+        }
+    }
+
+    /// 1 → 0 as `age` (fraction of the trail's life) goes 0 → 1.
+    fn fade(self, age: f64) -> f64 {
+        let age = age.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => 1.0 - age,
+            Easing::EaseOut => 1.0 - age * age,
+            Easing::Exponential => {
+                let end = (-Self::EXP_RATE).exp();
+                ((-Self::EXP_RATE * age).exp() - end) / (1.0 - end)
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 struct Peer {
     x: f64,
     y: f64,
     color: String,
+    rainbow: bool,
+}
+
+/// My own recent movement being played back (R), times relative to its start.
+struct Rewind {
+    points: Vec<TrailPoint>, // oldest first
+    started: f64,
+}
+
+impl Rewind {
+    fn duration(&self) -> f64 {
+        self.points.last().map_or(0.0, |p| p.t)
+    }
+
+    /// The stretch of trail on screen `at` ms into playback: what had been
+    /// drawn by then, back as far as a trail lives.
+    fn showing(&self, at: f64, life: f64) -> &[TrailPoint] {
+        let end = self.points.partition_point(|p| p.t <= at);
+        let start = self.points[..end].partition_point(|p| p.t < at - life);
+        &self.points[start..end]
+    }
+}
+
+/// A click marker, in world px.
+struct Marker {
+    x: f64,
+    y: f64,
+    color: String,
+    t: f64,
 }
 
 #[derive(Clone)]
@@ -30,25 +638,2186 @@ struct TrailPoint {
     t: f64,
 }
 
-#[wasm_bindgen(start)]
-pub fn run() -> Result<(), JsValue> {
+/// Spots dropped broadcasts from the server's sequence numbers, one
+/// tracker per lane (`Stamped::lossy`), as each is in order on its own.
+#[derive(Default)]
+struct SeqTracker {
+    next: Option<u64>,
+}
+
+impl SeqTracker {
+    /// Record `seq`, returning how many messages before it never arrived.
+    /// Stragglers count as nothing.
+    fn observe(&mut self, seq: u64) -> u64 {
+        match self.next {
+            Some(next) if seq < next => 0,
+            Some(next) => {
+                self.next = Some(seq + 1);
+                seq - next
+            }
+            None => {
+                self.next = Some(seq + 1);
+                0
+            }
+        }
+    }
+}
+
+/// The server connection. Handlers are kept so a reconnect can rewire them.
+struct Socket {
+    link: RefCell<Link>,
+    on_open: RefCell<Option<Function>>,
+    on_message: RefCell<Option<Function>>,
+    on_close: RefCell<Option<Function>>,
+    backoff: Cell<i32>,                    // ms before the next reconnect attempt
+    color: RefCell<String>, // asked for on (re)connect, so it's pinned from the start
+    resume: RefCell<Option<String>>, // from the last welcome, to come back as the same session
+    room: String,           // extra query for the room, see `Config::room`
+    webtransport: RefCell<Option<String>>, // tried first, until it fails to open once
+    sent: RefCell<ByteRate>,
+    received: RefCell<ByteRate>,
+}
+
+/// What a `Socket` is connected over.
+enum Link {
+    Ws(WebSocket),
+    Wt(Rc<Wt>),
+}
+
+impl Socket {
+    fn open(color: &str, config: &Config) -> Result<Self, JsValue> {
+        let room: String = config
+            .room
+            .iter()
+            .map(|(k, v)| format!("&{k}={}", String::from(js_sys::encode_uri_component(v))))
+            .collect();
+        let webtransport = config.webtransport.clone();
+        Ok(Self {
+            link: RefCell::new(connect(webtransport.as_deref(), &query(color, &room))?),
+            on_open: RefCell::new(None),
+            on_message: RefCell::new(None),
+            on_close: RefCell::new(None),
+            backoff: Cell::new(RECONNECT_MIN),
+            color: RefCell::new(color.to_string()),
+            resume: RefCell::new(None),
+            room,
+            webtransport: RefCell::new(webtransport),
+            sent: RefCell::default(),
+            received: RefCell::default(),
+        })
+    }
+
+    /// Send a message; one that won't serialize is logged and dropped.
+    fn send(&self, msg: &Msg) {
+        match serde_json::to_string(msg) {
+            Ok(txt) => {
+                self.sent.borrow_mut().add(perf_now(), txt.len());
+                match &*self.link.borrow() {
+                    Link::Ws(ws) => {
+                        let _ = ws.send_with_str(&txt);
+                    }
+                    Link::Wt(session) => session.send(&txt),
+                }
+            }
+            Err(e) => console::warn_1(&format!("dropping unserializable message: {e}").into()),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        match &*self.link.borrow() {
+            Link::Ws(ws) => ws.ready_state() == WebSocket::OPEN,
+            Link::Wt(session) => session.writer.borrow().is_some(),
+        }
+    }
+
+    /// Hang up; the close handler runs as for any drop.
+    fn close(&self) {
+        match &*self.link.borrow() {
+            Link::Ws(ws) => {
+                let _ = ws.close();
+            }
+            Link::Wt(session) => session.inner.close(),
+        }
+    }
+
+    /// Attach the stored handlers to the current socket.
+    fn rewire(self: &Rc<Self>) {
+        match &*self.link.borrow() {
+            Link::Ws(ws) => {
+                ws.set_onopen(self.on_open.borrow().as_ref());
+                ws.set_onmessage(self.on_message.borrow().as_ref());
+                ws.set_onclose(self.on_close.borrow().as_ref());
+            }
+            Link::Wt(session) => {
+                wasm_bindgen_futures::spawn_local(drive(self.clone(), session.clone()));
+            }
+        }
+    }
+
+    fn reconnect(self: &Rc<Self>) -> Result<(), JsValue> {
+        let mut query = query(&self.color.borrow(), &self.room);
+        if let Some(token) = self.resume.borrow().as_deref() {
+            query.push_str(&format!("&resume={token}"));
+        }
+        *self.link.borrow_mut() = connect(self.webtransport.borrow().as_deref(), &query)?;
+        self.rewire();
+        Ok(())
+    }
+
+    /// Whether `session` is still what this is connected over.
+    fn is_on(&self, session: &Rc<Wt>) -> bool {
+        matches!(&*self.link.borrow(), Link::Wt(s) if Rc::ptr_eq(s, session))
+    }
+
+    /// Run a handler with an event standing in for the websocket's.
+    fn fire(&self, handler: &RefCell<Option<Function>>, event: Result<impl JsCast, JsValue>) {
+        if let (Some(handler), Ok(event)) = (handler.borrow().as_ref(), event) {
+            let _ = handler.call1(&JsValue::NULL, event.unchecked_ref());
+        }
+    }
+}
+
+/// Query the server is connected with, tagged with the wire version this
+/// build speaks, the color we'd like pinned and the room to join.
+fn query(color: &str, room: &str) -> String {
+    let color = color.replace('#', "%23");
+    format!("?v={}&color={color}{room}", shared::VERSION)
+}
+
+/// Connect over WebTransport when the server offers it and the browser has
+/// it, the websocket otherwise.
+fn connect(webtransport: Option<&str>, query: &str) -> Result<Link, JsValue> {
+    if let Some(endpoint) = webtransport {
+        match WebTransport::new(&format!("{endpoint}{query}")) {
+            Ok(transport) => return Ok(Link::Wt(Rc::new(Wt::new(transport)))),
+            Err(e) => console::warn_2(&"no webtransport, using the websocket:".into(), &e),
+        }
+    }
+    Ok(Link::Ws(WebSocket::new(&format!("{WS_URL}{query}"))?))
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// The browser's, bound by hand: web-sys only has it behind
+    /// `--cfg=web_sys_unstable_apis`.
+    type WebTransport;
+
+    #[wasm_bindgen(constructor, catch)]
+    fn new(url: &str) -> Result<WebTransport, JsValue>;
+
+    #[wasm_bindgen(method, getter)]
+    fn ready(this: &WebTransport) -> js_sys::Promise;
+
+    #[wasm_bindgen(method, getter)]
+    fn datagrams(this: &WebTransport) -> Duplex;
+
+    #[wasm_bindgen(method, js_name = createBidirectionalStream)]
+    fn create_bidirectional_stream(this: &WebTransport) -> js_sys::Promise;
+
+    #[wasm_bindgen(method)]
+    fn close(this: &WebTransport);
+
+    /// A bidirectional stream, or a session's datagrams: both a pair of streams.
+    type Duplex;
+
+    #[wasm_bindgen(method, getter)]
+    fn readable(this: &Duplex) -> ReadableStream;
+
+    #[wasm_bindgen(method, getter)]
+    fn writable(this: &Duplex) -> WritableStream;
+}
+
+/// A WebTransport session with the server. Messages go both ways a line
+/// each on one stream, except moves the server lets go as datagrams.
+struct Wt {
+    inner: WebTransport,
+    writer: RefCell<Option<WritableStreamDefaultWriter>>, // the stream's, once it's open
+}
+
+impl Wt {
+    fn new(transport: WebTransport) -> Self {
+        Self {
+            inner: transport,
+            writer: RefCell::new(None),
+        }
+    }
+
+    /// Queue a line on the stream, if it's open.
+    fn send(&self, txt: &str) {
+        if let Some(writer) = self.writer.borrow().as_ref() {
+            let line = js_sys::Uint8Array::from(format!("{txt}\n").as_bytes());
+            let _ = writer.write_with_chunk(&line);
+        }
+    }
+}
+
+/// Open `session`'s stream and hand what arrives to `socket`'s handlers,
+/// as the websocket's events would be, until it ends. A session that never
+/// opens falls back to the websocket, for good: whatever stopped it (no
+/// udp out of this network, say) is likely to again.
+async fn drive(socket: Rc<Socket>, session: Rc<Wt>) {
+    let opened = async {
+        JsFuture::from(session.inner.ready()).await?;
+        JsFuture::from(session.inner.create_bidirectional_stream()).await
+    };
+    let stream: Duplex = match opened.await {
+        Ok(stream) => stream.unchecked_into(),
+        Err(e) => {
+            console::warn_2(&"webtransport didn't open, using the websocket:".into(), &e);
+            *socket.webtransport.borrow_mut() = None;
+            if socket.is_on(&session) {
+                let _ = socket.reconnect();
+            }
+            return;
+        }
+    };
+    *session.writer.borrow_mut() = stream.writable().get_writer().ok();
+    // the server only hears of the stream once something is written on it
+    session.send("");
+    socket.fire(&socket.on_open, web_sys::Event::new("open"));
+
+    wasm_bindgen_futures::spawn_local({
+        let (socket, session) = (socket.clone(), session.clone());
+        let datagrams = session.inner.datagrams().readable();
+        async move {
+            read(&datagrams, |bytes| {
+                if socket.is_on(&session) {
+                    let txt = String::from_utf8_lossy(bytes);
+                    socket.fire(&socket.on_message, message_event(&txt));
+                }
+                true
+            })
+            .await;
+        }
+    });
+
+    let mut lines = Lines::default();
+    let mut closed = None;
+    read(&stream.readable(), |bytes| {
+        let Some(got) = lines.push(bytes).filter(|_| socket.is_on(&session)) else {
+            return false;
+        };
+        for line in got {
+            // what the server closed with, which a websocket would have in its close frame
+            if line.starts_with(r#"{"kind":"close""#) {
+                if let Some(Msg::Close { code, reason }) = Msg::decode(&line) {
+                    closed = Some((code, reason));
+                }
+                continue;
+            }
+            socket.fire(&socket.on_message, message_event(&line));
+        }
+        true
+    })
+    .await;
+
+    session.writer.borrow_mut().take();
+    session.inner.close();
+    if socket.is_on(&session) {
+        // 1006: gone without a word, like a websocket that dropped
+        let (code, reason) = closed.unwrap_or((1006, String::new()));
+        let init = web_sys::CloseEventInit::new();
+        init.set_code(code);
+        init.set_reason(&reason);
+        let event = CloseEvent::new_with_event_init_dict("close", &init);
+        socket.fire(&socket.on_close, event);
+    }
+}
+
+/// Feed each chunk `stream` yields to `each` until it ends, fails, or
+/// `each` says to stop.
+async fn read(stream: &ReadableStream, mut each: impl FnMut(&[u8]) -> bool) {
+    let reader: ReadableStreamDefaultReader = stream.get_reader().unchecked_into();
+    while let Ok(chunk) = JsFuture::from(reader.read()).await {
+        let chunk: ReadableStreamReadResult = chunk.unchecked_into();
+        if chunk.get_done().unwrap_or(true) {
+            break;
+        }
+        if !each(&js_sys::Uint8Array::new(&chunk.get_value()).to_vec()) {
+            break;
+        }
+    }
+}
+
+fn message_event(txt: &str) -> Result<MessageEvent, JsValue> {
+    let init = web_sys::MessageEventInit::new();
+    init.set_data(&txt.into());
+    MessageEvent::new_with_event_init_dict("message", &init)
+}
+
+/// `Math.random`, or a xorshift64* stream when seeded so runs repeat.
+struct Rng(Option<u64>);
+
+impl Rng {
+    fn new(seed: Option<u64>) -> Self {
+        // xorshift gets stuck on 0; mixing in a constant keeps seed 0 usable
+        Self(seed.map(|s| (s ^ 0x9e37_79b9_7f4a_7c15).max(1)))
+    }
+
+    /// Uniform in [0, 1).
+    fn next(&mut self) -> f64 {
+        let Some(x) = self.0.as_mut() else {
+            return Math::random();
+        };
+        *x ^= *x >> 12;
+        *x ^= *x << 25;
+        *x ^= *x >> 27;
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Bytes moved over the last `BYTE_WINDOW` ms.
+#[derive(Default)]
+struct ByteRate {
+    samples: VecDeque<(f64, usize)>,
+    total: usize,
+}
+
+impl ByteRate {
+    fn add(&mut self, t: f64, bytes: usize) {
+        self.samples.push_back((t, bytes));
+        self.total += bytes;
+    }
+
+    fn per_sec(&mut self, now: f64) -> f64 {
+        while let Some(&(t, bytes)) = self.samples.front() {
+            if t >= now - BYTE_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+            self.total -= bytes;
+        }
+        self.total as f64 * 1_000.0 / BYTE_WINDOW
+    }
+}
+
+fn fmt_rate(bytes_per_sec: f64) -> String {
+    if bytes_per_sec < 1_000.0 {
+        format!("{bytes_per_sec:.0} B/s")
+    } else {
+        format!("{:.1} kB/s", bytes_per_sec / 1_000.0)
+    }
+}
+
+/// Peers bucketed by position on a uniform grid, rebuilt every frame, so
+/// "who's within r of here" looks at a few cells rather than everyone.
+struct Grid {
+    cell: f64,
+    points: Vec<(String, f64, f64)>,
+    cells: HashMap<(i32, i32), Vec<usize>>, // indices into `points`
+}
+
+impl Grid {
+    fn new(cell: f64) -> Self {
+        Self {
+            cell,
+            points: Vec::new(),
+            cells: HashMap::new(),
+        }
+    }
+
+    fn key(&self, x: f64, y: f64) -> (i32, i32) {
+        (
+            (x / self.cell).floor() as i32,
+            (y / self.cell).floor() as i32,
+        )
+    }
+
+    fn clear(&mut self) {
+        self.points.clear();
+        self.cells.clear();
+    }
+
+    fn insert(&mut self, id: &str, x: f64, y: f64) {
+        let key = self.key(x, y);
+        self.cells.entry(key).or_default().push(self.points.len());
+        self.points.push((id.to_string(), x, y));
+    }
+
+    /// Ids of everyone within `r` of (`x`, `y`).
+    fn within(&self, x: f64, y: f64, r: f64) -> impl Iterator<Item = &str> {
+        self.near(x, y, r).map(|(id, ..)| id.as_str())
+    }
+
+    /// Everyone within `r` of (`x`, `y`), with where they are.
+    fn near(&self, x: f64, y: f64, r: f64) -> impl Iterator<Item = &(String, f64, f64)> {
+        let ((x0, y0), (x1, y1)) = (self.key(x - r, y - r), self.key(x + r, y + r));
+        (x0..=x1)
+            .flat_map(move |cx| (y0..=y1).map(move |cy| (cx, cy)))
+            .filter_map(|key| self.cells.get(&key))
+            .flatten()
+            .map(|&i| &self.points[i])
+            .filter(move |(_, px, py)| (px - x).hypot(py - y) <= r)
+    }
+}
+
+/// Axis-aligned box, used to track what a frame drew.
+#[derive(Clone, Copy)]
+struct Rect {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+}
+
+impl Rect {
+    fn around(x: f64, y: f64, r: f64) -> Self {
+        Self {
+            x0: x - r,
+            y0: y - r,
+            x1: x + r,
+            y1: y + r,
+        }
+    }
+
+    fn grow(&mut self, x: f64, y: f64, r: f64) {
+        self.x0 = self.x0.min(x - r);
+        self.y0 = self.y0.min(y - r);
+        self.x1 = self.x1.max(x + r);
+        self.y1 = self.y1.max(y + r);
+    }
+
+    fn contains(&self, x: f64, y: f64) -> bool {
+        (self.x0..=self.x1).contains(&x) && (self.y0..=self.y1).contains(&y)
+    }
+
+    fn offset(self, dx: f64, dy: f64) -> Self {
+        Self {
+            x0: self.x0 + dx,
+            y0: self.y0 + dy,
+            x1: self.x1 + dx,
+            y1: self.y1 + dy,
+        }
+    }
+}
+
+/// One line of a recorded session: a relayed message and when it was seen (ms).
+#[derive(Deserialize)]
+struct Recorded {
+    t: f64,
+    #[serde(flatten)]
+    msg: Msg,
+}
+
+/// A recorded session loaded for local playback.
+struct Replay {
+    moves: Vec<(f64, Packet)>, // sorted, t relative to the first move
+    duration: f64,
+    at: f64,
+    scrubbing: bool,
+}
+
+impl Replay {
+    /// Parse NDJSON, skipping malformed lines and anything that isn't a move.
+    fn parse(txt: &str) -> Option<Self> {
+        let mut moves: Vec<(f64, Packet)> = txt
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Recorded>(line).ok())
+            .filter_map(|rec| match rec.msg {
+                Msg::Move(pkt) => Some((rec.t, pkt)),
+                _ => None,
+            })
+            .collect();
+        moves.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let t0 = moves.first()?.0;
+        for (t, _) in moves.iter_mut() {
+            *t -= t0;
+        }
+        let duration = moves.last()?.0;
+
+        Some(Self {
+            moves,
+            duration,
+            at: 0.0,
+            scrubbing: false,
+        })
+    }
+
+    /// Rebuild every peer's position and trail as of `self.at` and draw them.
+    fn draw(&self, ctx: &CanvasRenderingContext2d, w_css: f64, h_css: f64, config: &Config) {
+        let (life, easing) = (config.trail_life, config.easing);
+        let to = self.moves.partition_point(|(t, _)| *t <= self.at);
+        let from = self.moves.partition_point(|(t, _)| *t < self.at - life);
+
+        // trails: recent moves (old -> new, fading)
+        for (t, pkt) in &self.moves[from..to] {
+            let (x, y) = pkt.pos();
+            let alpha = easing.fade((self.at - t) / life);
+            ctx.set_global_alpha(alpha);
+            ctx.begin_path();
+            ctx.set_fill_style_str(&pkt.color);
+            ctx.arc(
+                x as f64 * w_css,
+                y as f64 * h_css,
+                config.cursor_radius * alpha,
+                0.0,
+                2.0 * PI,
+            )
+            .unwrap();
+            ctx.fill();
+        }
+        ctx.set_global_alpha(1.0);
+
+        // cursors: last known position of every pointer seen so far, bar lifted ones
+        let mut heads = HashMap::<String, &Packet>::new();
+        for (_, pkt) in &self.moves[..to] {
+            if pkt.lifted {
+                heads.remove(&pkt.cursor_key());
+            } else {
+                heads.insert(pkt.cursor_key(), pkt);
+            }
+        }
+        for pkt in heads.values() {
+            let (x, y) = pkt.pos();
+            ctx.begin_path();
+            ctx.set_fill_style_str(&pkt.color);
+            ctx.arc(
+                x as f64 * w_css,
+                y as f64 * h_css,
+                config.cursor_radius,
+                0.,
+                2. * PI,
+            )
+            .unwrap();
+            ctx.fill();
+        }
+    }
+}
+
+/// Audio graph handles: osc -> gain -> filter -> speakers, and through a
+/// reverb alongside when `Config::reverb` is set.
+#[cfg(feature = "audio")]
+struct Audio {
+    ctx: AudioContext,
+    osc: OscillatorNode,
+    gain: GainNode,
+}
+
+#[cfg(feature = "audio")]
+impl Audio {
+    /// Build the graph and start it, silent until something moves. `wave`
+    /// (0..1) picks the waveform.
+    fn new(config: &Config, wave: f64) -> Result<Self, JsValue> {
+        let ctx = AudioContext::new()?;
+        let osc: OscillatorNode = ctx.create_oscillator()?;
+
+        // pick a “pleasant” wave-form once per run
+        let waves = [
+            OscillatorType::Sine,
+            OscillatorType::Sawtooth,
+            OscillatorType::Square,
+        ];
+        osc.set_type(waves[(wave * waves.len() as f64).floor() as usize]);
+
+        osc.frequency().set_value(200.0);
+        let gain: GainNode = ctx.create_gain()?;
+        gain.gain().set_value(0.0);
+
+        let filter: BiquadFilterNode = ctx.create_biquad_filter()?;
+        filter.set_type(BiquadFilterType::Lowpass);
+        filter.frequency().set_value(1000.0);
+        filter.q().set_value(0.7);
+
+        // osc -> gain -> filter -> dry -> speakers (+ filter -> reverb -> speakers)
+        let dry: GainNode = ctx.create_gain()?;
+        osc.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&filter)?;
+        filter.connect_with_audio_node(&dry)?;
+        dry.connect_with_audio_node(&ctx.destination())?;
+        if config.reverb > 0.0 {
+            dry.gain().set_value((1.0 - config.reverb) as f32);
+            add_reverb(&ctx, &filter, config.reverb, &mut Rng::new(config.seed))?;
+        }
+
+        osc.start()?;
+        Ok(Self { ctx, osc, gain })
+    }
+
+    /// Map a movement speed onto the oscillator's pitch and volume.
+    fn voice(&self, speed: f64, volume: f64) {
+        self.osc
+            .frequency()
+            .set_value((200.0 + speed * 800.0) as f32);
+
+        // ramp rather than jump, so starts and stops don't click: quick attack, slow release
+        let level = (speed * 2.0).min(volume).clamp(0.0, MAX_VOLUME) as f32;
+        let gain = self.gain.gain();
+        let (now, from) = (self.ctx.current_time(), gain.value());
+        let ramp = if level > from {
+            AUDIO_ATTACK
+        } else {
+            AUDIO_RELEASE
+        };
+        let _ = gain.cancel_scheduled_values(now);
+        let _ = gain.set_value_at_time(from, now);
+        let _ = gain.linear_ramp_to_value_at_time(level, now + ramp);
+    }
+
+    /// Short blip for a marker landing, on its own oscillator so the movement
+    /// voice carries on undisturbed.
+    fn chirp(&self, volume: f64) -> Result<(), JsValue> {
+        let ctx = &self.ctx;
+        let osc = ctx.create_oscillator()?;
+        osc.frequency().set_value(880.0);
+        let gain = ctx.create_gain()?;
+        let now = ctx.current_time();
+        gain.gain()
+            .set_value_at_time(volume.clamp(0.0, MAX_VOLUME) as f32, now)?;
+        gain.gain()
+            .linear_ramp_to_value_at_time(0.0, now + MARK_CHIRP)?;
+        osc.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(&ctx.destination())?;
+        osc.start()?;
+        osc.stop_with_when(now + MARK_CHIRP)?;
+        Ok(())
+    }
+}
+
+/// Built without the `audio` feature: the same calls, and no sound.
+#[cfg(not(feature = "audio"))]
+struct Audio;
+
+#[cfg(not(feature = "audio"))]
+impl Audio {
+    fn new(_config: &Config, _wave: f64) -> Result<Self, JsValue> {
+        Ok(Audio)
+    }
+
+    fn voice(&self, _speed: f64, _volume: f64) {}
+
+    fn chirp(&self, _volume: f64) -> Result<(), JsValue> {
+        Ok(())
+    }
+}
+
+/// Presentation mode, toggled with L: whoever moved most over the last
+/// second glows and everyone else dims. Glow eases, so a change of leader
+/// cross-fades rather than snapping.
+#[derive(Default)]
+struct Spotlight {
+    on: bool,
+    activity: HashMap<String, f64>, // px moved, decaying over ~SPOT_WINDOW
+    glow: HashMap<String, f64>,     // 0..=1
+}
+
+impl Spotlight {
+    fn moved(&mut self, id: &str, px: f64) {
+        if px > 0.0 {
+            *self.activity.entry(id.to_string()).or_default() += px;
+        }
+    }
+
+    fn update(&mut self, dt: f64) {
+        let decay = (-dt / SPOT_WINDOW).exp();
+        self.activity.retain(|_, a| {
+            *a *= decay;
+            *a > 0.01
+        });
+
+        let leader = leader(&self.activity).map(str::to_string);
+        if let Some(id) = &leader {
+            self.glow.entry(id.clone()).or_default();
+        }
+        let k = (SPOT_EASE * dt).min(1.0);
+        self.glow.retain(|id, g| {
+            let goal = if Some(id) == leader.as_ref() {
+                1.0
+            } else {
+                0.0
+            };
+            *g += (goal - *g) * k;
+            goal > 0.0 || *g > 0.01
+        });
+    }
+
+    /// Alpha multiplier and glow (0..=1) for `id`'s cursor.
+    fn emphasis(&self, id: &str) -> (f64, f64) {
+        if !self.on {
+            return (1.0, 0.0);
+        }
+        let g = self.glow.get(id).copied().unwrap_or(0.0);
+        (SPOT_DIM + (1.0 - SPOT_DIM) * g, g)
+    }
+
+    fn forget(&mut self, id: &str) {
+        self.activity.remove(id);
+        self.glow.remove(id);
+    }
+}
+
+/// Most active id, if anyone moved enough to count.
+fn leader(activity: &HashMap<String, f64>) -> Option<&str> {
+    activity
+        .iter()
+        .filter(|(_, a)| **a >= SPOT_MIN)
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(id, _)| id.as_str())
+}
+
+/// Side panel listing who's here, toggled with P. Rows are only touched when
+/// someone joins, leaves or changes color, never per frame.
+struct Roster {
+    panel: HtmlElement,
+    rows: HashMap<String, (HtmlElement, String)>, // id -> row, color it shows
+}
+
+impl Roster {
+    /// Row key for the local user; never a server-assigned id.
+    const YOU: &str = "you";
+
+    fn new(document: &web_sys::Document) -> Result<Self, JsValue> {
+        let panel: HtmlElement = document.create_element("div")?.dyn_into()?;
+        panel.style().set_css_text(
+            "position: fixed; right: 12px; top: 60px; padding: 8px 12px; display: none; \
+             background: #1e1e1e; color: #aaa; font: 12px monospace; border-radius: 4px;",
+        );
+        document.body().unwrap().append_child(&panel)?;
+        Ok(Self {
+            panel,
+            rows: HashMap::new(),
+        })
+    }
+
+    fn toggle(&self) {
+        let style = self.panel.style();
+        let shown = style.get_property_value("display").as_deref() == Ok("block");
+        let _ = style.set_property("display", if shown { "none" } else { "block" });
+    }
+
+    /// Add `id`, or recolor its swatch if it changed.
+    fn upsert(&mut self, document: &web_sys::Document, id: &str, color: &str) {
+        // peers pick their own, so nothing but a color gets near a style
+        if !is_hex_color(color) {
+            return;
+        }
+        if let Some((row, shown)) = self.rows.get_mut(id) {
+            if shown != color {
+                if let Some(swatch) = row.first_element_child() {
+                    let _ = swatch
+                        .unchecked_into::<HtmlElement>()
+                        .style()
+                        .set_property("background", color);
+                }
+                *shown = color.to_string();
+            }
+            return;
+        }
+        let Ok(row) = Self::row(document, id, color) else {
+            return;
+        };
+        // you first, everyone else in order of arrival
+        if id == Self::YOU {
+            let _ = self.panel.prepend_with_node_1(&row);
+        } else {
+            let _ = self.panel.append_child(&row);
+        }
+        self.rows.insert(id.to_string(), (row, color.to_string()));
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some((row, _)) = self.rows.remove(id) {
+            row.remove();
+        }
+    }
+
+    /// Drop the rows of everyone but us not in `ids`.
+    fn keep_only(&mut self, ids: &HashSet<&str>) {
+        self.rows.retain(|id, (row, _)| {
+            let keep = id == Self::YOU || ids.contains(id.as_str());
+            if !keep {
+                row.remove();
+            }
+            keep
+        });
+    }
+
+    fn row(document: &web_sys::Document, id: &str, color: &str) -> Result<HtmlElement, JsValue> {
+        let row: HtmlElement = document.create_element("div")?.dyn_into()?;
+        row.style().set_css_text("padding: 2px 0; cursor: pointer;");
+        if id != Self::YOU {
+            row.set_attribute("data-id", id)?; // clicked -> follow
+        }
+        let swatch: HtmlElement = document.create_element("span")?.dyn_into()?;
+        swatch.style().set_css_text(
+            "display: inline-block; width: 10px; height: 10px; margin-right: 8px; \
+             border-radius: 50%;",
+        );
+        swatch.style().set_property("background", color)?;
+        row.append_child(&swatch)?;
+        row.append_with_str_1(id)?;
+        Ok(row)
+    }
+}
+
+/// Everything the event handlers share, behind one `Rc<RefCell<..>>`.
+struct ClientState {
+    config: Config,
+    ws: Rc<Socket>,
+    ctx: CanvasRenderingContext2d,
+    document: web_sys::Document,
+    roster: Roster,
+    spotlight: Spotlight,
+    audio: Audio,
+    w_css: f64,
+    h_css: f64,
+
+    // self
+    color: String,
+    my_id: Option<String>, // only ever from the welcome, so it holds whether or not we're echoed
+    target: (f64, f64),
+    pos: (f64, f64),
+    my_trail: VecDeque<TrailPoint>,
+    my_history: VecDeque<TrailPoint>, // the last `REWIND_SPAN` of it, for R
+    my_path: VecDeque<(f64, f64)>,    // everywhere since the last export, for V
+    rewind: Option<Rewind>,
+    keys: HashSet<String>,           // held direction keys (arrows / WASD)
+    idle: f64,                       // ms since the last pointer or key input
+    fingers: HashMap<i32, Finger>,   // pointers down besides the primary, by pointer id
+    fingers_sent: f64,               // when moved fingers last went out
+    pointer_filter: Option<OneEuro>, // see `Config::smooth`
+    unacked: VecDeque<(f64, f64)>,   // targets sent but not yet echoed back, oldest first
+
+    // peers: latest target and interpolated position
+    peers_t: HashMap<String, Peer>,
+    peers_p: HashMap<String, Peer>,
+    trails: HashMap<String, VecDeque<TrailPoint>>,
+    arrivals: HashMap<String, VecDeque<TrailPoint>>, // timestamped targets, when delaying
+    voices: HashMap<String, f64>, // smoothed on-screen speed (px/ms), drives their sound
+    jitter: HashMap<String, Jitter>, // how evenly their moves arrive
+    departed: HashSet<String>,    // left since we connected; moves queued behind are stale
+    lifted: HashMap<String, u64>, // cursors lifted, and the first move seq that can follow
+    grid: Grid,                   // where peers were drawn this frame, for proximity lookups
+
+    // click markers on screen, oldest first, and where the pointer went down
+    marks: Vec<Marker>,
+    press: Option<(f64, f64)>,
+    clicked: Option<((f64, f64), f64)>, // world position and time of a click not yet marked
+    shake: f64,                         // px the view currently wobbles by, decaying
+
+    // camera: world offset of the top-left corner, and whom it tracks
+    view: (f64, f64),
+    follow: Option<String>,
+
+    // recorded session being played back instead of the live board
+    replay: Option<Replay>,
+    seek: HtmlInputElement, // only shown while replaying
+
+    status: Option<String>,        // shown while disconnected
+    notice: Option<(String, f64)>, // last server notice, and when to stop showing it
+    frozen: bool,                  // a moderator has paused everyone's moves
+    online: Option<usize>,         // live connections, as last announced by the server
+    room: Option<String>,          // joined, as told by the server
+    seq: [SeqTracker; 2],          // reliable lane, then moves
+    rtt: VecDeque<f64>,            // recent round trips (ms), oldest first
+    budget: FrameBudget,           // how long frames take to draw, and the detail that allows
+    debug: bool,                   // debug overlay, toggled with `
+
+    // frame bookkeeping
+    last_ts: f64,             // last timestamp - used to calc dt for speed
+    dirty: Option<Vec<Rect>>, // screen-space boxes drawn last frame; `None` forces a full clear
+    audio_acc: f64,           // time since audio params were last pushed
+    ping_acc: f64,            // time since the last round-trip probe
+    last_heard: f64,          // when the server last sent anything, ticks included
+    send_every: f64,          // ms between moves, as asked by the server; 0 = every change
+    last_send: f64,           // when the last move went out
+    send_pending: bool,       // a move was held back by `send_every`
+    last_saved: f64,          // when `Session` was last written
+}
+
+impl ClientState {
+    fn to_world(&self, x: f64, y: f64) -> (f64, f64) {
+        (x + self.view.0, y + self.view.1)
+    }
+
+    /// Tell everyone where we're heading (CSS px, world space), no more often
+    /// than the server's tick rate; `frame` sends whatever was held back.
+    fn send_target(&mut self) {
+        let now = perf_now();
+        if now - self.last_send < self.send_every {
+            self.send_pending = true;
+            return;
+        }
+        self.last_send = now;
+        self.send_pending = false;
+        let (x, y) = self.target;
+        if self.unacked.len() == UNACKED_MAX {
+            self.unacked.pop_front();
+        }
+        self.unacked.push_back((x, y));
+        let pkt = move_packet(&self.color, &self.config, x / self.w_css, y / self.h_css);
+        self.ws.send(&Msg::Move(pkt));
+    }
+
+    /// Another pointer went down (a second finger, say): it gets the first
+    /// free sub-id and is its own cursor to everyone else until it lifts.
+    fn finger_down(&mut self, id: i32, x: f64, y: f64) {
+        self.idle = 0.0;
+        let Some(sub) = free_pointer(self.fingers.values().map(|f| f.sub)) else {
+            return; // more fingers than the server takes; this one stays local-only
+        };
+        let at = self.to_world(x, y);
+        self.fingers.insert(
+            id,
+            Finger {
+                sub,
+                at,
+                moved: true,
+            },
+        );
+    }
+
+    fn finger_moved(&mut self, id: i32, x: f64, y: f64) {
+        self.idle = 0.0;
+        let at = self.to_world(x, y);
+        if let Some(finger) = self.fingers.get_mut(&id) {
+            finger.at = at;
+            finger.moved = true;
+        }
+    }
+
+    /// Lifted or cancelled: say where it ended so peers drop the cursor.
+    fn finger_up(&mut self, id: i32) {
+        if let Some(finger) = self.fingers.remove(&id) {
+            self.send_finger(&finger, true);
+        }
+    }
+
+    fn send_finger(&self, finger: &Finger, lifted: bool) {
+        let (x, y) = finger.at;
+        let mut pkt = move_packet(&self.color, &self.config, x / self.w_css, y / self.h_css);
+        pkt.pointer = Some(finger.sub);
+        pkt.lifted = lifted;
+        self.ws.send(&Msg::Move(pkt));
+    }
+
+    /// Fingers that moved since the last go, at the same pace as `send_target`.
+    fn flush_fingers(&mut self, now: f64) {
+        if now - self.fingers_sent < self.send_every {
+            return;
+        }
+        self.fingers_sent = now;
+        for finger in self.fingers.values() {
+            if finger.moved {
+                self.send_finger(finger, false);
+            }
+        }
+        self.fingers.values_mut().for_each(|f| f.moved = false);
+    }
+
+    /// Drop everything held for one peer cursor.
+    fn forget_cursor(&mut self, key: &str) {
+        self.peers_t.remove(key);
+        self.peers_p.remove(key);
+        self.trails.remove(key);
+        self.arrivals.remove(key);
+        self.voices.remove(key);
+        self.jitter.remove(key);
+        self.spotlight.forget(key);
+    }
+
+    fn on_message(&mut self, stamped: Stamped) {
+        // gaps only mean something when we get every kind
+        if let (Some(seq), None) = (stamped.seq, &self.config.kinds) {
+            let missed = self.seq[stamped.lossy as usize].observe(seq);
+            if missed > 0 {
+                console::warn_1(&format!("missed {missed} broadcasts").into());
+            }
+        }
+
+        // a lift on the stream can overtake moves still in flight as datagrams
+        let lossy_seq = stamped.seq.filter(|_| stamped.lossy);
+        match stamped.msg {
+            // the leave overtakes moves the server had already queued for us
+            Msg::Move(pkt) if self.departed.contains(&pkt.id) => {}
+            Msg::Move(pkt)
+                if lossy_seq.is_some_and(|seq| {
+                    (self.lifted.get(&pkt.cursor_key())).is_some_and(|&next| seq < next)
+                }) => {}
+            Msg::Move(pkt) => {
+                // quantized positions are smoothed out by the interpolation in `frame`
+                let (x, y) = pkt.pos();
+
+                // store target for other players, each of their pointers a cursor
+                if is_peer(self.my_id.as_deref(), &pkt.id) {
+                    let key = pkt.cursor_key();
+                    if pkt.lifted {
+                        self.forget_cursor(&key);
+                        if let Some(next) = stamped.supersedes {
+                            self.lifted.insert(key, next);
+                        }
+                        return;
+                    }
+                    let (x, y) = (x as f64 * self.w_css, y as f64 * self.h_css);
+                    let t = perf_now();
+                    self.jitter.entry(key.clone()).or_default().arrived(t);
+                    if self.config.interp_delay > 0.0 {
+                        let buf = self.arrivals.entry(key.clone()).or_default();
+                        buf.push_back(TrailPoint { x, y, t });
+                    }
+                    self.roster.upsert(&self.document, &pkt.id, &pkt.color);
+                    self.peers_t.insert(
+                        key,
+                        Peer {
+                            x,
+                            y,
+                            color: pkt.color,
+                            rainbow: pkt.rainbow,
+                        },
+                    );
+                } else if pkt.pointer.is_none() {
+                    // our own echo: the cursor ran ahead on prediction, and the
+                    // server only disagrees when it changed the move (e.g. clamped it)
+                    let echo = (x as f64 * self.w_css, y as f64 * self.h_css);
+                    if !reconcile(&mut self.unacked, echo, RECONCILE_SLACK) {
+                        self.target = echo;
+                        self.pos = echo;
+                    }
+                }
+            }
+            Msg::Mark(mark) => {
+                let (x, y) = (mark.x as f64 * self.w_css, mark.y as f64 * self.h_css);
+                if self.config.shake {
+                    self.shake = self.shake.max(self.shake_for(x, y));
+                }
+                self.marks.push(Marker {
+                    x,
+                    y,
+                    color: mark.color,
+                    t: perf_now(),
+                });
+                let _ = self.audio.chirp(self.config.volume);
+            }
+            Msg::Welcome(welcome) => self.on_welcome(welcome),
+            Msg::Notice { text } => self.notice = Some((text, perf_now() + NOTICE_LIFE)),
+            Msg::Snapshot { peers } => self.on_snapshot(peers),
+            Msg::Presence { online } => self.online = Some(online),
+            Msg::Freeze { frozen } => self.frozen = frozen,
+            Msg::Leave { id } => {
+                let keys = self.peers_t.keys().filter(|k| cursor_owner(k) == id);
+                let keys: Vec<String> = keys.cloned().collect();
+                for key in keys.iter().chain([&id]) {
+                    self.forget_cursor(key);
+                }
+                self.roster.remove(&id);
+                self.lifted.retain(|key, _| cursor_owner(key) != id);
+                self.departed.insert(id);
+            }
+            Msg::Pong { t } => {
+                if self.rtt.len() == RTT_SAMPLES {
+                    self.rtt.pop_front();
+                }
+                self.rtt.push_back(perf_now() - t);
+            }
+            _ => {}
+        }
+    }
+
+    /// Adopt what the server assigned this connection.
+    fn on_welcome(&mut self, welcome: Welcome) {
+        self.my_id = welcome.id;
+        self.departed.clear(); // a new connection, with nothing queued from before
+        self.lifted.clear();
+        *self.ws.resume.borrow_mut() = welcome.resume;
+        self.unacked.clear();
+        if let Some(color) = welcome.color.filter(|c| *c != self.color) {
+            self.roster.upsert(&self.document, Roster::YOU, &color);
+            self.color = color;
+        }
+        if welcome.online.is_some() {
+            self.online = welcome.online;
+        }
+        self.room = welcome.room;
+        self.frozen = welcome.frozen.unwrap_or(false);
+        if let Some(version) = welcome.version {
+            console::log_1(&format!("connected to server {version}").into());
+        }
+        let tick = welcome
+            .tick_rate
+            .map_or(0.0, |hz| 1_000.0 / hz.max(1) as f64);
+        self.send_every = tick.max(self.config.frame_every);
+    }
+
+    /// Seat everyone already on the board, trails and all, so they don't pop
+    /// in bare as they next move. It's the whole board: anyone we still show
+    /// who isn't in it left while we were away, their leave unheard.
+    fn on_snapshot(&mut self, peers: Vec<PeerState>) {
+        let seated: HashSet<String> = peers.iter().map(|p| p.id.clone()).collect();
+        let gone: Vec<String> = (self.peers_t.keys().chain(self.trails.keys()))
+            .filter(|key| !seated.contains(*key))
+            .cloned()
+            .collect();
+        for key in &gone {
+            self.forget_cursor(key);
+        }
+        let owners = seated.iter().map(|key| cursor_owner(key)).collect();
+        self.roster.keep_only(&owners);
+
+        let now = perf_now();
+        let (w, h) = (self.w_css, self.h_css);
+        for p in peers {
+            let owner = cursor_owner(&p.id);
+            if !is_peer(self.my_id.as_deref(), owner) {
+                continue;
+            }
+            self.roster.upsert(&self.document, owner, &p.color);
+            let trail = p.trail.iter().map(|&(x, y, ago)| TrailPoint {
+                x: x as f64 * w,
+                y: y as f64 * h,
+                t: now - ago as f64,
+            });
+            self.trails.insert(p.id.clone(), trail.collect());
+            let peer = Peer {
+                x: p.x as f64 * w,
+                y: p.y as f64 * h,
+                color: p.color,
+                rainbow: false,
+            };
+            self.peers_p.insert(p.id.clone(), peer.clone());
+            self.peers_t.insert(p.id, peer);
+        }
+    }
+
+    /// Swap between the dark and light palettes.
+    fn toggle_theme(&mut self) {
+        self.config.theme = self.config.theme.toggled();
+        self.config.background = self.config.theme.background().to_string();
+        self.dirty = None; // repaint the whole board, not just last frame's boxes
+    }
+
+    /// Scale the cursor size preference by `factor`, and remember it.
+    fn resize_cursor(&mut self, factor: f64) {
+        let size = (self.config.cursor_radius / CURSOR_RADIUS * factor).clamp(MIN_SIZE, MAX_SIZE);
+        self.config.cursor_radius = CURSOR_RADIUS * size;
+        if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+            let _ = storage.set_item(SIZE_KEY, &size.to_string());
+        }
+    }
+
+    /// Switch to a picked color. The server pins colors per connection and
+    /// only accepts a change every few seconds, so peers may lag behind.
+    fn set_color(&mut self, color: String) {
+        if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+            let _ = storage.set_item(COLOR_KEY, &color);
+        }
+        self.ws.send(&Msg::SetColor {
+            color: color.clone(),
+        });
+        self.roster.upsert(&self.document, Roster::YOU, &color);
+        *self.ws.color.borrow_mut() = color.clone();
+        self.color = color;
+        self.send_target();
+    }
+
+    fn on_pointer_move(&mut self, x: f64, y: f64) {
+        self.idle = 0.0;
+        let (x, y) = match self.pointer_filter.as_mut() {
+            Some(filter) => filter.filter(perf_now(), (x, y)),
+            None => (x, y),
+        };
+        self.target = self.to_world(x, y);
+        self.send_target();
+    }
+
+    /// Shake for a marker at world (`x`, `y`): strongest in the middle of
+    /// the view, none from a quarter of the screen out.
+    fn shake_for(&self, x: f64, y: f64) -> f64 {
+        let (cx, cy) = self.to_world(self.w_css * 0.5, self.h_css * 0.5);
+        let range = 0.25 * self.w_css.min(self.h_css);
+        SHAKE_PX * (1.0 - (x - cx).hypot(y - cy) / range).max(0.0)
+    }
+
+    /// A press and release close together is a click: drop a marker there.
+    /// The marker waits out `MARK_WAIT`: a second click by then makes it a
+    /// double-click, which follows rather than marks.
+    fn on_pointer_up(&mut self, x: f64, y: f64) {
+        let Some((x0, y0)) = self.press.take() else {
+            return;
+        };
+        if (x - x0).hypot(y - y0) > MARK_SLOP {
+            return;
+        }
+        let now = perf_now();
+        self.clicked = match self.clicked {
+            Some((_, t)) if now - t < MARK_WAIT => None,
+            _ => Some((self.to_world(x, y), now)),
+        };
+    }
+
+    /// Drop the marker for a click that stayed single.
+    fn flush_click(&mut self, now: f64) {
+        let Some(((x, y), t)) = self.clicked else {
+            return;
+        };
+        if now - t < MARK_WAIT {
+            return;
+        }
+        self.clicked = None;
+        self.ws.send(&Msg::Mark(Mark {
+            id: String::new(), // server fills in
+            color: self.color.clone(),
+            x: (x / self.w_css) as f32,
+            y: (y / self.h_css) as f32,
+        }));
+    }
+
+    /// Follow the peer under the pointer, if any.
+    fn on_dblclick(&mut self, x: f64, y: f64) {
+        self.clicked = None; // one slower than `MARK_WAIT` marks once, not twice
+        let (x, y) = self.to_world(x, y);
+        let hit = self.grid.within(x, y, self.config.cursor_radius).next();
+        if let Some(id) = hit {
+            self.follow = Some(id.to_string());
+        }
+    }
+
+    /// Download where my cursor has been since the last export as an SVG
+    /// path, simplified, then start a fresh one.
+    fn export_path(&mut self) {
+        let points = simplify(self.my_path.make_contiguous(), PATH_TOLERANCE);
+        let Some(svg) = svg_path(&points, &self.color, self.config.cursor_radius) else {
+            return;
+        };
+        match download(&self.document, "femtanyl.svg", "image/svg+xml", &svg) {
+            Ok(()) => self.my_path.clear(),
+            Err(e) => console::error_1(&e),
+        }
+    }
+
+    fn load_replay(&mut self, replay: Replay) {
+        self.seek.set_max(&replay.duration.to_string());
+        let _ = self.seek.style().set_property("display", "block");
+        self.replay = Some(replay);
+    }
+
+    fn close_replay(&mut self) {
+        if self.replay.take().is_some() {
+            let _ = self.seek.style().set_property("display", "none");
+        }
+    }
+
+    fn frame(&mut self, time: f64) {
+        // low-power cap: skip this one and let `dt` span the frames we didn't draw
+        if !self.last_ts.is_nan() && !frame_due(time - self.last_ts, self.config.frame_every) {
+            return;
+        }
+        let started = perf_now();
+        let (w_css, h_css) = (self.w_css, self.h_css);
+
+        // clear canvas
+        match self.dirty.take() {
+            Some(rects) if self.config.dirty_clear => {
+                for r in rects {
+                    clear_rect(&self.ctx, r, &self.config.background);
+                }
+            }
+            _ => clear_canvas(&self.ctx, w_css, h_css, &self.config.background),
+        }
+        let mut drawn = Vec::<Rect>::new();
+
+        let dt = if self.last_ts.is_nan() {
+            0.0
+        } else {
+            time - self.last_ts
+        };
+        self.last_ts = time;
+
+        let now = time;
+
+        // ─── replay ───
+        // a loaded recording takes over the board until Escape
+        if let Some(r) = self.replay.as_mut() {
+            if !r.scrubbing {
+                r.at = (r.at + dt).min(r.duration);
+                self.seek.set_value_as_number(r.at);
+            }
+            r.draw(&self.ctx, w_css, h_css, &self.config);
+            self.audio.voice(0.0, self.config.volume);
+            return;
+        }
+
+        let (mut vx, mut vy) = self.update_camera(dt);
+        if self.shake > 0.05 {
+            self.shake *= (-dt / SHAKE_DECAY).exp();
+            let phase = now * SHAKE_HZ * 2.0 * PI;
+            vx += self.shake * phase.sin();
+            vy += self.shake * (phase * 1.3).cos();
+        } else {
+            self.shake = 0.0;
+        }
+        self.ctx.save();
+        self.ctx.translate(-vx, -vy).unwrap();
+
+        // fastest mover this frame drives the sound
+        let mut audio_speed: f64 = 0.0;
+
+        self.spotlight.update(dt);
+        self.draw_peers(dt, now, &mut drawn, &mut audio_speed);
+        self.grid.clear();
+        for (id, p) in &self.peers_p {
+            self.grid.insert(id, p.x, p.y);
+        }
+        self.apply_keys(dt);
+        self.apply_gamepad(dt);
+        self.idle += dt;
+        self.apply_idle_pull(dt);
+        self.apply_screensaver(dt);
+        self.apply_magnet(dt);
+        self.draw_self(dt, now, &mut drawn, &mut audio_speed);
+        self.draw_rewind(now, &mut drawn);
+        self.draw_marks(now, &mut drawn);
+
+        // push audio params at a fixed rate rather than every rendered frame
+        self.audio_acc += dt;
+        if self.audio_acc >= AUDIO_INTERVAL {
+            self.audio_acc %= AUDIO_INTERVAL;
+            let hush = if self.screensaving() {
+                SAVER_VOLUME
+            } else {
+                1.0
+            };
+            let volume = self.config.volume * hush;
+            self.audio.voice(audio_speed, volume);
+        }
+
+        if self.send_pending && now - self.last_send >= self.send_every {
+            self.send_target();
+        }
+        self.flush_fingers(now);
+        self.flush_click(now);
+
+        self.ping_acc += dt;
+        if self.ping_acc >= PING_INTERVAL {
+            self.ping_acc %= PING_INTERVAL;
+            self.ws.send(&Msg::Ping { t: now });
+        }
+
+        // open but silent, not even ticking: the pipe has stalled, so start over
+        // (closing goes through `onclose`, and its backoff, like any drop)
+        if now - self.last_heard > STALL_AFTER && self.ws.is_open() {
+            console::warn_1(&"server went quiet, reconnecting".into());
+            self.last_heard = now;
+            self.ws.close();
+        }
+
+        self.ctx.restore();
+
+        let mut screen: Vec<Rect> = drawn.into_iter().map(|r| r.offset(-vx, -vy)).collect();
+
+        // ─── overlay (screen space) ───
+        if self.notice.as_ref().is_some_and(|(_, until)| now >= *until) {
+            self.notice = None;
+        }
+        let status = self
+            .status
+            .as_deref()
+            .or(self.notice.as_ref().map(|(text, _)| text.as_str()))
+            .or(self
+                .frozen
+                .then_some("frozen: a moderator has paused the cursors"));
+        if status.is_some() || self.online.is_some() {
+            self.ctx.set_font("12px monospace");
+            self.ctx.set_fill_style_str(self.config.theme.text());
+            if let Some(msg) = status {
+                self.ctx.fill_text(msg, 12.0, h_css - 12.0).unwrap();
+            }
+            if let Some(n) = self.online {
+                let label = match &self.room {
+                    // only worth naming when the page picked one
+                    Some(room) if self.config.room.iter().any(|(k, _)| *k == "room") => {
+                        format!("{n} online in {room}")
+                    }
+                    _ => format!("{n} online"),
+                };
+                self.ctx.set_text_align("right");
+                self.ctx
+                    .fill_text(&label, w_css - 12.0, h_css - 12.0)
+                    .unwrap();
+                self.ctx.set_text_align("start");
+            }
+            screen.push(Rect {
+                x0: 0.0,
+                y0: h_css - STATUS_HEIGHT,
+                x1: w_css,
+                y1: h_css,
+            });
+        }
+        if self.debug {
+            let spark = draw_sparkline(&self.ctx, &self.rtt, w_css - SPARK_W - 12.0, 12.0);
+            screen.push(self.draw_link(now, w_css - 12.0, spark.y1 + 14.0));
+            screen.push(spark);
+        }
+        self.dirty = Some(screen);
+        self.save_session(now);
+        let budget = self.config.frame_budget;
+        self.budget.record(perf_now() - started, now, budget);
+    }
+
+    /// What the connection negotiated and how much it's moving, right-aligned
+    /// at `x`. Returns the box it covered.
+    fn draw_link(&self, now: f64, x: f64, y: f64) -> Rect {
+        let link = match &*self.ws.link.borrow() {
+            Link::Ws(ws) => {
+                let protocol = match ws.protocol() {
+                    p if p.is_empty() => "json".to_string(),
+                    p => p,
+                };
+                let deflate = ws.extensions().contains("permessage-deflate");
+                format!(
+                    "{protocol} text, deflate {}",
+                    if deflate { "on" } else { "off" }
+                )
+            }
+            Link::Wt(_) => "webtransport, moves as datagrams".to_string(),
+        };
+        let lines = [
+            link,
+            format!(
+                "↑ {} ↓ {}",
+                fmt_rate(self.ws.sent.borrow_mut().per_sec(now)),
+                fmt_rate(self.ws.received.borrow_mut().per_sec(now)),
+            ),
+            format!("last heard {:.1} s ago", (now - self.last_heard) / 1_000.0),
+            format!(
+                "{} quality, {:.1} ms/frame",
+                self.budget.quality.label(),
+                self.budget.avg
+            ),
+        ];
+        self.ctx.set_font("12px monospace");
+        self.ctx.set_fill_style_str(self.config.theme.text());
+        self.ctx.set_text_align("right");
+        for (i, line) in lines.iter().enumerate() {
+            self.ctx.fill_text(line, x, y + i as f64 * 14.0).unwrap();
+        }
+        self.ctx.set_text_align("start");
+        Rect {
+            x0: x - 220.0,
+            y0: y - 12.0,
+            x1: x,
+            y1: y + lines.len() as f64 * 14.0,
+        }
+    }
+
+    /// Pan toward the followed peer, or back home once they're gone.
+    fn update_camera(&mut self, dt: f64) -> (f64, f64) {
+        let goal = match self.follow.as_ref().map(|id| self.peers_p.get(id)) {
+            Some(Some(p)) => (p.x - self.w_css * 0.5, p.y - self.h_css * 0.5),
+            Some(None) => {
+                self.follow = None;
+                (0.0, 0.0)
+            }
+            None => (0.0, 0.0),
+        };
+        let k = (FOLLOW_EASE * dt).min(1.0);
+        self.view.0 += (goal.0 - self.view.0) * k;
+        self.view.1 += (goal.1 - self.view.1) * k;
+        self.view
+    }
+
+    /// Animate peers toward their targets and draw them with their trails.
+    /// Everyone is animated, but only those in view are drawn, and only the
+    /// busiest `max_trails` of those get a trail.
+    fn draw_peers(&mut self, dt: f64, now: f64, drawn: &mut Vec<Rect>, audio_speed: &mut f64) {
+        let ctx = &self.ctx;
+        let (life, speed_factor) = (self.config.trail_life, self.config.speed_factor);
+        let radius = self.config.cursor_radius;
+        let quality = self.budget.quality;
+        let pad = CULL_MARGIN + radius;
+        let view = Rect {
+            x0: self.view.0 - pad,
+            y0: self.view.1 - pad,
+            x1: self.view.0 + self.w_css + pad,
+            y1: self.view.1 + self.h_css + pad,
+        };
+        let trailed = self.config.max_trails.map(|n| busiest(&self.voices, n));
+        for (id, tgt) in self.peers_t.iter() {
+            let current = self.peers_p.entry(id.clone()).or_insert(tgt.clone());
+            let before = (current.x, current.y);
+
+            // interpolate: between buffered arrivals a fixed delay back, or chase the latest
+            let delayed = self.arrivals.get_mut(id).and_then(|buf| {
+                let at = now - self.config.interp_delay;
+                prune_arrivals(buf, at);
+                sample_arrivals(buf, at)
+            });
+            match delayed {
+                Some(pos) => (current.x, current.y) = pos,
+                None if self.config.calm.is_some() => (current.x, current.y) = (tgt.x, tgt.y),
+                None => {
+                    ((current.x, current.y), _) =
+                        advance((current.x, current.y), (tgt.x, tgt.y), dt, speed_factor);
+                }
+            }
+
+            let moved = (current.x - before.0).hypot(current.y - before.1);
+            self.spotlight.moved(id, moved);
+            let (dim, glow) = self.spotlight.emphasis(id);
+            let glow = quality.glow(glow, self.config.bloom);
+
+            // draw, in the latest color they sent, washed out if they're lagging
+            let hue;
+            let mut color = if tgt.rainbow {
+                hue = rainbow_color(now, &self.config);
+                &hue
+            } else {
+                &tgt.color
+            };
+            let grey;
+            let laggy = self.jitter.get(id).map_or(0.0, Jitter::laggy);
+            if laggy > 0.05 {
+                grey = desaturate(color, laggy * JITTER_GREY);
+                color = &grey;
+            }
+            // sound from the speed we actually show, so gaps in their updates don't warble
+            if dt > 0.0 {
+                let voice = self.voices.entry(id.clone()).or_default();
+                *voice = smooth(*voice, moved / dt, dt, self.config.audio_smooth);
+                *audio_speed = audio_speed.max(*voice);
+            }
+
+            // keep recording the trail while it's hidden, so it's whole when it shows
+            let q = self.trails.entry(id.clone()).or_default();
+            q.push_back(TrailPoint {
+                x: current.x,
+                y: current.y,
+                t: now,
+            });
+
+            // drop expired points
+            prune_trail(q, now, life);
+
+            if self.config.cull && !view.contains(current.x, current.y) {
+                continue;
+            }
+            if !self.config.ghost {
+                draw_head(ctx, current.x, current.y, radius, color, dim, glow);
+            }
+
+            // 2. draw trail (old → new, fading)
+            let mut bounds = Rect::around(current.x, current.y, radius + glow * GLOW_BLUR);
+            if trailed.as_ref().is_none_or(|t| t.contains(id)) {
+                draw_trail(ctx, &self.config, quality, q, now, color, dim, &mut bounds);
+            }
+            drawn.push(bounds);
+        }
+    }
+
+    /// Held direction keys nudge the target like pointer input.
+    fn apply_keys(&mut self, dt: f64) {
+        if !self.keys.is_empty() && typing(&self.document) {
+            self.keys.clear();
+        }
+        let (kx, ky) = self
+            .keys
+            .iter()
+            .filter_map(|code| key_dir(code))
+            .fold((0.0, 0.0), |(ax, ay), (x, y)| (ax + x, ay + y));
+        if (kx != 0.0 || ky != 0.0) && dt > 0.0 {
+            let len = (kx * kx + ky * ky).sqrt();
+            let step = self.config.key_speed * dt;
+            self.nudge_target(kx / len * step, ky / len * step);
+        }
+    }
+
+    /// The first connected gamepad's left stick steers like held keys.
+    /// Polled every frame, so pads coming and going need no bookkeeping.
+    fn apply_gamepad(&mut self, dt: f64) {
+        if dt <= 0.0 {
+            return;
+        }
+        if let Some((gx, gy)) = gamepad_stick() {
+            let step = self.config.pad_speed * dt;
+            self.nudge_target(gx * step, gy * step);
+        }
+    }
+
+    /// Shift the target by a screen-space offset, as input, keeping it on
+    /// screen wherever the view has panned to.
+    fn nudge_target(&mut self, dx: f64, dy: f64) {
+        let (tx, ty) = self.target;
+        let (vx, vy) = self.view;
+        self.target = (
+            (tx + dx).clamp(vx, vx + self.w_css),
+            (ty + dy).clamp(vy, vy + self.h_css),
+        );
+        self.idle = 0.0;
+        self.send_target();
+    }
+
+    /// Once idle long enough, ease the target toward the middle of the view.
+    fn apply_idle_pull(&mut self, dt: f64) {
+        let Some(after) = self.config.idle_pull else {
+            return;
+        };
+        if self.idle < after || self.screensaving() {
+            return;
+        }
+        let (cx, cy) = self.to_world(self.w_css * 0.5, self.h_css * 0.5);
+        let (tx, ty) = self.target;
+        if (cx - tx).abs() < 0.5 && (cy - ty).abs() < 0.5 {
+            return;
+        }
+        let k = (IDLE_PULL_EASE * dt).min(1.0);
+        self.target = (tx + (cx - tx) * k, ty + (cy - ty) * k);
+        self.send_target();
+    }
+
+    /// Whether `Config::screensaver` is steering. Any input resets `idle`,
+    /// so it lets go on the next frame.
+    fn screensaving(&self) -> bool {
+        self.config.screensaver && self.idle >= SAVER_AFTER && self.peers_t.len() <= SAVER_PEERS
+    }
+
+    /// While screensaving, ease the target onto a slow loop around the middle
+    /// of the view. Sent like any move, so the board looks lived in.
+    fn apply_screensaver(&mut self, dt: f64) {
+        if !self.screensaving() || dt <= 0.0 {
+            return;
+        }
+        let (lx, ly) = saver_path(self.idle - SAVER_AFTER);
+        let (px, py) = self.to_world(
+            self.w_css * (0.5 + lx * SAVER_REACH),
+            self.h_css * (0.5 + ly * SAVER_REACH),
+        );
+        let (tx, ty) = self.target;
+        let k = (SAVER_EASE * dt).min(1.0);
+        self.target = (tx + (px - tx) * k, ty + (py - ty) * k);
+        self.send_target();
+    }
+
+    /// Drift the target with `Config::magnet`'s pull from peers in reach.
+    /// Others see it like any move, so everyone running it flocks.
+    fn apply_magnet(&mut self, dt: f64) {
+        let Some((strength, radius)) = self.config.magnet else {
+            return;
+        };
+        let (tx, ty) = self.target;
+        let near = self.grid.near(tx, ty, radius).map(|(_, x, y)| (*x, *y));
+        let (fx, fy) = magnet_force((tx, ty), near, strength, radius);
+        if dt <= 0.0 || (fx == 0.0 && fy == 0.0) {
+            return;
+        }
+        self.target = (tx + fx * dt, ty + fy * dt);
+        self.send_target();
+    }
+
+    /// Snapshot what a reload should pick up again.
+    fn session(&self) -> Session {
+        Session {
+            saved: js_sys::Date::now(),
+            room: room_name(&self.config).map(str::to_string),
+            pos: self.pos,
+            view: self.view,
+            color: self.color.clone(),
+            light: self.config.theme == Theme::Light,
+            ghost: self.config.ghost,
+            spotlight: self.spotlight.on,
+            debug: self.debug,
+        }
+    }
+
+    /// Write the session to localStorage, at most every `SESSION_SAVE`.
+    fn save_session(&mut self, now: f64) {
+        if now - self.last_saved >= SESSION_SAVE {
+            self.last_saved = now;
+            self.write_session();
+        }
+    }
+
+    fn write_session(&self) {
+        let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) else {
+            return;
+        };
+        if let Ok(json) = serde_json::to_string(&self.session()) {
+            let _ = storage.set_item(SESSION_KEY, &json);
+        }
+    }
+
+    /// Carry on from a saved session: same spot, same toggles. Settings the
+    /// url asks for outright still win.
+    fn resume(&mut self, session: Session, win: &web_sys::Window) {
+        self.pos = session.pos;
+        self.target = session.pos;
+        self.view = session.view;
+        let light = self.config.theme == Theme::Light;
+        if session.light != light
+            && query_param(win, "theme")
+                .or(query_param(win, "bg"))
+                .is_none()
+        {
+            self.toggle_theme();
+        }
+        if query_param(win, "ghost").is_none() {
+            self.config.ghost = session.ghost;
+        }
+        self.spotlight.on = session.spotlight;
+        self.debug = session.debug;
+    }
+
+    /// Play my last few seconds back as a see-through ghost, once, in real time.
+    fn start_rewind(&mut self) {
+        let now = perf_now();
+        if let Some(t0) = self.my_history.front().map(|p| p.t) {
+            let points = self
+                .my_history
+                .iter()
+                .map(|p| TrailPoint { t: p.t - t0, ..*p });
+            self.rewind = Some(Rewind {
+                points: points.collect(),
+                started: now,
+            });
+        }
+    }
+
+    fn draw_rewind(&mut self, now: f64, drawn: &mut Vec<Rect>) {
+        let Some(rewind) = &self.rewind else {
+            return;
+        };
+        let (life, radius) = (self.config.trail_life, self.config.cursor_radius);
+        let at = now - rewind.started;
+        if at > rewind.duration() + life {
+            self.rewind = None;
+            return;
+        }
+        let shown = rewind.showing(at, life);
+        let Some(head) = shown.last() else {
+            return;
+        };
+        let ctx = &self.ctx;
+        let mut bounds = Rect::around(head.x, head.y, radius);
+        ctx.set_fill_style_str(&self.color);
+        for p in shown {
+            bounds.grow(p.x, p.y, radius);
+            let alpha = self.config.easing.fade((at - p.t) / life);
+            ctx.set_global_alpha(alpha * REWIND_ALPHA);
+            ctx.begin_path();
+            ctx.arc(p.x, p.y, radius * alpha, 0.0, 2.0 * PI).unwrap();
+            ctx.fill();
+        }
+        ctx.set_global_alpha(1.0);
+        drawn.push(bounds);
+    }
+
+    /// Expanding, fading rings where people clicked.
+    fn draw_marks(&mut self, now: f64, drawn: &mut Vec<Rect>) {
+        self.marks.retain(|m| now - m.t < MARK_LIFE);
+        let ctx = &self.ctx;
+        ctx.set_line_width(2.0);
+        for m in &self.marks {
+            let age = ((now - m.t) / MARK_LIFE).clamp(0.0, 1.0);
+            let r = MARK_RADIUS * (1.0 - (1.0 - age).powi(2)); // eases out
+            ctx.set_global_alpha(1.0 - age);
+            ctx.set_stroke_style_str(&m.color);
+            ctx.begin_path();
+            ctx.arc(m.x, m.y, r, 0., 2. * PI).unwrap();
+            ctx.stroke();
+            drawn.push(Rect::around(m.x, m.y, MARK_RADIUS + 2.0));
+        }
+        ctx.set_global_alpha(1.0);
+    }
+
+    /// Move the local cursor toward its target and draw it with its trail.
+    fn draw_self(&mut self, dt: f64, now: f64, drawn: &mut Vec<Rect>, audio_speed: &mut f64) {
+        let ctx = &self.ctx;
+        let radius = self.config.cursor_radius;
+        let quality = self.budget.quality;
+
+        // pos
+        let speed;
+        let before = self.pos;
+        (self.pos, speed) = advance(self.pos, self.target, dt, self.config.speed_factor);
+        let (x, y) = self.pos;
+        self.spotlight
+            .moved(Roster::YOU, (x - before.0).hypot(y - before.1));
+        let (dim, glow) = self.spotlight.emphasis(Roster::YOU);
+        let glow = quality.glow(glow, self.config.bloom);
+
+        // record my own trail
+        self.my_history.push_back(TrailPoint { x, y, t: now });
+        prune_trail(&mut self.my_history, now, REWIND_SPAN);
+        let last = self.my_path.back();
+        if last.is_none_or(|&(lx, ly)| (x - lx).hypot(y - ly) >= PATH_STEP) {
+            if self.my_path.len() == PATH_MAX {
+                self.my_path.pop_front();
+            }
+            self.my_path.push_back((x, y));
+        }
+        let q = &mut self.my_trail;
+        q.push_back(TrailPoint { x, y, t: now });
+        prune_trail(q, now, self.config.trail_life);
+
+        // draw my trail
+        let hue;
+        let color = if self.config.rainbow {
+            hue = rainbow_color(now, &self.config);
+            &hue
+        } else {
+            &self.color
+        };
+        let mut bounds = Rect::around(x, y, radius + glow * GLOW_BLUR);
+        draw_trail(ctx, &self.config, quality, q, now, color, dim, &mut bounds);
+
+        // draw
+        if !self.config.ghost {
+            draw_head(ctx, x, y, radius, color, dim, glow);
+            // fingers are kept in world space, like the view we're drawing in
+            for &Finger { at: (fx, fy), .. } in self.fingers.values() {
+                draw_head(ctx, fx, fy, radius, color, dim, glow);
+                drawn.push(Rect::around(fx, fy, radius + glow * GLOW_BLUR));
+            }
+        }
+        drawn.push(bounds);
+
+        // sound from speed
+        *audio_speed = audio_speed.max(speed);
+    }
+}
+
+/// Whether a frame capped to one per `every` ms should render, `elapsed` ms
+/// after the last one that did.
+fn frame_due(elapsed: f64, every: f64) -> bool {
+    elapsed >= every - FRAME_SLACK
+}
+
+/// A cursor's fading trail, old → new, growing `bounds` over it. Lower
+/// quality draws less of it, and at `Low` a single line rather than a dot
+/// per point.
+#[allow(clippy::too_many_arguments)]
+fn draw_trail(
+    ctx: &CanvasRenderingContext2d,
+    config: &Config,
+    quality: Quality,
+    q: &VecDeque<TrailPoint>,
+    now: f64,
+    color: &str,
+    dim: f64,
+    bounds: &mut Rect,
+) {
+    let (life, radius) = (
+        config.trail_life * quality.trail_share(),
+        config.cursor_radius,
+    );
+    let shown = q.iter().filter(|p| now - p.t <= life);
+    if quality == Quality::Low {
+        ctx.set_global_alpha(0.5 * dim);
+        ctx.set_stroke_style_str(color);
+        ctx.set_line_width(radius * 0.5);
+        ctx.begin_path();
+        for p in shown {
+            bounds.grow(p.x, p.y, radius);
+            ctx.line_to(p.x, p.y);
+        }
+        ctx.stroke();
+    } else {
+        let bloom = match (config.bloom_trails, quality) {
+            (true, Quality::High) => config.bloom,
+            _ => 0.0,
+        };
+        if bloom > 0.0 {
+            ctx.set_shadow_color(color);
+            ctx.set_shadow_blur(bloom);
+        }
+        ctx.set_fill_style_str(color);
+        for p in shown {
+            bounds.grow(p.x, p.y, radius + bloom);
+            let alpha = config.easing.fade((now - p.t) / life); // 1 → 0
+            ctx.set_global_alpha(alpha * dim);
+            ctx.begin_path();
+            ctx.arc(p.x, p.y, radius * alpha, 0.0, 2.0 * PI).unwrap();
+            ctx.fill();
+        }
+        ctx.set_shadow_blur(0.0);
+    }
+    ctx.set_global_alpha(1.0); // reset!
+}
+
+/// A cursor's head, faded by `alpha` and haloed in its own color by `glow`.
+fn draw_head(
+    ctx: &CanvasRenderingContext2d,
+    x: f64,
+    y: f64,
+    radius: f64,
+    color: &str,
+    alpha: f64,
+    glow: f64,
+) {
+    ctx.set_global_alpha(alpha);
+    if glow > 0.0 {
+        ctx.set_shadow_color(color);
+        ctx.set_shadow_blur(glow * GLOW_BLUR);
+    }
+    ctx.begin_path();
+    ctx.set_fill_style_str(color);
+    ctx.arc(x, y, radius, 0., 2. * PI).unwrap();
+    ctx.fill();
+    ctx.set_shadow_blur(0.0);
+    ctx.set_global_alpha(1.0);
+}
+
+/// Whether a message from `id` is someone else's. The welcome comes before
+/// any broadcast, so `my_id` is known by the time our own moves could come
+/// back; if the server doesn't echo them, nothing here depends on it.
+fn is_peer(my_id: Option<&str>, id: &str) -> bool {
+    my_id != Some(id)
+}
+
+/// Match the server's echo of one of our moves against those still in
+/// flight. A match acknowledges it and everything sent before; no match
+/// means the server overrode us, and we should snap to `echo`.
+fn reconcile(unacked: &mut VecDeque<(f64, f64)>, echo: (f64, f64), slack: f64) -> bool {
+    let hit = unacked
+        .iter()
+        .position(|&(x, y)| (x - echo.0).hypot(y - echo.1) <= slack);
+    match hit {
+        Some(i) => {
+            unacked.drain(..=i);
+            true
+        }
+        None => {
+            unacked.clear();
+            false
+        }
+    }
+}
+
+/// Ids of the `n` peers whose cursors are moving fastest.
+fn busiest(voices: &HashMap<String, f64>, n: usize) -> HashSet<String> {
+    let mut by_speed: Vec<_> = voices.iter().collect();
+    by_speed.sort_unstable_by(|a, b| b.1.total_cmp(a.1));
+    by_speed
+        .into_iter()
+        .take(n)
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+/// Running spread of the gaps between a peer's moves, to tell a steady
+/// connection from one that stutters. Exponentially weighted, so it's a few
+/// floats per peer and follows the connection as it changes.
+#[derive(Default)]
+struct Jitter {
+    last: Option<f64>, // when the previous move arrived
+    mean: Option<f64>, // ms between moves
+    var: f64,
+}
+
+impl Jitter {
+    fn arrived(&mut self, t: f64) {
+        let gap = self.last.replace(t).map(|last| t - last);
+        // a long gap is the peer holding still; it says nothing about the link
+        let Some(gap) = gap.filter(|g| (0.0..JITTER_PAUSE).contains(g)) else {
+            return;
+        };
+        let Some(mean) = self.mean else {
+            self.mean = Some(gap);
+            return;
+        };
+        let d = gap - mean;
+        self.mean = Some(mean + JITTER_WEIGHT * d);
+        self.var = (1.0 - JITTER_WEIGHT) * (self.var + JITTER_WEIGHT * d * d);
+    }
+
+    /// 0 for a steady stream of moves, up to 1 at `JITTER_BAD` of spread.
+    fn laggy(&self) -> f64 {
+        (self.var.sqrt() / JITTER_BAD).min(1.0)
+    }
+}
+
+/// One-euro filter (Casiez et al.) for a 2-D position: a low-pass whose
+/// cutoff rises with speed, so a resting pointer is steadied while quick
+/// flicks still land without lag.
+struct OneEuro {
+    min_cutoff: f64,         // Hz, at rest
+    beta: f64,               // extra Hz per px/s of speed
+    t: f64,                  // ms, last sample
+    pos: Option<(f64, f64)>, // filtered, none before the first sample
+    vel: (f64, f64),         // px/s, filtered
+}
+
+impl OneEuro {
+    const D_CUTOFF: f64 = 1.0; // Hz, for the velocity estimate itself
+
+    fn new(min_cutoff: f64, beta: f64) -> Self {
+        Self {
+            min_cutoff: min_cutoff.max(0.01),
+            beta: beta.max(0.0),
+            t: 0.0,
+            pos: None,
+            vel: (0.0, 0.0),
+        }
+    }
+
+    /// Smoothing factor for one step of `dt` s at `cutoff` Hz.
+    fn alpha(cutoff: f64, dt: f64) -> f64 {
+        let tau = 1.0 / (2.0 * PI * cutoff);
+        1.0 / (1.0 + tau / dt)
+    }
+
+    /// Filter a sample `(x, y)` taken at `t` ms.
+    fn filter(&mut self, t: f64, (x, y): (f64, f64)) -> (f64, f64) {
+        let Some((px, py)) = self.pos else {
+            (self.t, self.pos) = (t, Some((x, y)));
+            return (x, y);
+        };
+        let dt = (t - self.t) / 1_000.0;
+        if dt <= 0.0 {
+            return (px, py);
+        }
+        let (vx, vy) = self.vel;
+        let a_d = Self::alpha(Self::D_CUTOFF, dt);
+        let vx = vx + a_d * ((x - px) / dt - vx);
+        let vy = vy + a_d * ((y - py) / dt - vy);
+        let a = Self::alpha(self.min_cutoff + self.beta * vx.hypot(vy), dt);
+        let out = (px + a * (x - px), py + a * (y - py));
+        (self.t, self.pos, self.vel) = (t, Some(out), (vx, vy));
+        out
+    }
+}
+
+/// Ease `prev` toward `value` over `dt` ms with time constant `tau` ms.
+fn smooth(prev: f64, value: f64, dt: f64, tau: f64) -> f64 {
+    if tau <= 0.0 {
+        return value;
+    }
+    prev + (value - prev) * (1.0 - (-dt / tau).exp())
+}
+
+/// Step `current` toward `target`, covering `speed_factor` of the remaining
+/// distance per ms without overshooting. Returns the new position and speed.
+fn advance(
+    current: (f64, f64),
+    target: (f64, f64),
+    dt: f64,
+    speed_factor: f64,
+) -> ((f64, f64), f64) {
+    let dx = target.0 - current.0;
+    let dy = target.1 - current.1;
+    let dist = (dx * dx + dy * dy).sqrt();
+    if dist <= STOP_RADIUS || dt <= 0.0 {
+        return (current, 0.0);
+    }
+
+    let speed = dist * speed_factor;
+    let step = (speed * dt).min(dist);
+    let pos = (current.0 + dx / dist * step, current.1 + dy / dist * step);
+    (pos, speed)
+}
+
+/// Velocity (px/ms) `me` is pulled with by peers at `near`: toward each by
+/// `strength` (away if negative), fading to nothing at `radius`. Peers right
+/// on top of `me` give no direction, so add nothing.
+fn magnet_force(
+    me: (f64, f64),
+    near: impl Iterator<Item = (f64, f64)>,
+    strength: f64,
+    radius: f64,
+) -> (f64, f64) {
+    near.fold((0.0, 0.0), |(fx, fy), (x, y)| {
+        let (dx, dy) = (x - me.0, y - me.1);
+        let dist = dx.hypot(dy);
+        if dist < 1.0 || dist >= radius {
+            return (fx, fy);
+        }
+        let k = strength * (1.0 - dist / radius) / dist;
+        (fx + dx * k, fy + dy * k)
+    })
+}
+
+/// Where the screensaver's Lissajous loop is, `t` ms in, as -1..=1 either
+/// way from centre. Starts in the middle so easing onto it is short.
+fn saver_path(t: f64) -> (f64, f64) {
+    let (px, py) = SAVER_PERIOD;
+    let x = (t / px * 2.0 * PI).sin();
+    let y = (t / py * 2.0 * PI).sin() * (t / px * PI).cos();
+    (x, y)
+}
+
+/// Position at time `at` from buffered arrivals: linear between the two
+/// around it, held at the ends.
+fn sample_arrivals(buf: &VecDeque<TrailPoint>, at: f64) -> Option<(f64, f64)> {
+    let after = buf.iter().position(|p| p.t > at).unwrap_or(buf.len());
+    match (after.checked_sub(1).map(|i| &buf[i]), buf.get(after)) {
+        (Some(a), Some(b)) => {
+            let k = (at - a.t) / (b.t - a.t);
+            Some((a.x + (b.x - a.x) * k, a.y + (b.y - a.y) * k))
+        }
+        (Some(p), None) | (None, Some(p)) => Some((p.x, p.y)),
+        (None, None) => None,
+    }
+}
+
+/// Forget arrivals that can no longer be sampled at or after `at`.
+fn prune_arrivals(buf: &mut VecDeque<TrailPoint>, at: f64) {
+    while buf.get(1).is_some_and(|p| p.t <= at) {
+        buf.pop_front();
+    }
+}
+
+/// Drop trail points older than `life` ms.
+fn prune_trail(q: &mut VecDeque<TrailPoint>, now: f64, life: f64) {
+    while q.front().is_some_and(|p| now - p.t > life) {
+        q.pop_front();
+    }
+}
+
+#[wasm_bindgen(start)]
+pub fn run() -> Result<(), JsValue> {
+    // the standalone page has a `#wb` canvas; embedders call `mount` on their own
+    let document = web_sys::window().unwrap().document().unwrap();
+    if let Some(canvas) = document.get_element_by_id("wb") {
+        mount(canvas.dyn_into()?);
+    }
+    Ok(())
+}
+
+/// Start a board on `canvas`, sized to the box the page laid it out in.
+/// Tunables are fetched from the server first; defaults if that fails.
+#[wasm_bindgen]
+pub fn mount(canvas: HtmlCanvasElement) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let served = fetch_tunables().await.unwrap_or_default();
+        if let Err(e) = start(canvas, served) {
+            console::error_1(&e);
+        }
+    });
+}
+
+/// The server's `/config`, if it answers with something we can read.
+async fn fetch_tunables() -> Option<Tunables> {
+    let win = web_sys::window()?;
+    let resp: Response = JsFuture::from(win.fetch_with_str(CONFIG_URL))
+        .await
+        .ok()?
+        .dyn_into()
+        .ok()?;
+    if !resp.ok() {
+        return None;
+    }
+    let txt = JsFuture::from(resp.text().ok()?).await.ok()?.as_string()?;
+    serde_json::from_str(&txt).ok()
+}
+
+fn start(canvas: HtmlCanvasElement, served: Tunables) -> Result<(), JsValue> {
     // Prepare window and canvas
     let win = web_sys::window().unwrap();
     let dpr = win.device_pixel_ratio(); // include device pixel ratio for higher visual quality
+    let config = Config::from_query(&win, served);
+    let document = win.document().unwrap();
 
-    let canvas: HtmlCanvasElement = win
-        .document()
-        .unwrap()
-        .get_element_by_id("wb")
-        .unwrap()
-        .dyn_into()?;
-
-    // Resize canvas to fit screen (high resolution)
-    let w_css = win.inner_width()?.as_f64().unwrap();
-    let h_css = win.inner_height()?.as_f64().unwrap();
+    // Resize canvas to fill its box (high resolution), or the window if it has none yet
+    let (w_css, h_css) = match (canvas.client_width(), canvas.client_height()) {
+        (w, h) if w > 0 && h > 0 => (w as f64, h as f64),
+        _ => (
+            win.inner_width()?.as_f64().unwrap(),
+            win.inner_height()?.as_f64().unwrap(),
+        ),
+    };
 
     canvas.set_width((w_css * dpr) as u32);
     canvas.set_height((h_css * dpr) as u32);
+    // fingers are cursors here, not page scrolls or pinch zooms
+    canvas.style().set_property("touch-action", "none")?;
 
     // keep the on-screen box in CSS pixels
     canvas
@@ -62,257 +2831,412 @@ pub fn run() -> Result<(), JsValue> {
     ctx.scale(dpr, dpr)?;
 
     // ─── Audio set-up ───
-    let audio_ctx = Rc::new(AudioContext::new()?);
-    let oscillator: OscillatorNode = audio_ctx.create_oscillator()?;
-
-    // pick a “pleasant” wave-form once per run
-    let waves = [
-        OscillatorType::Sine,
-        OscillatorType::Sawtooth,
-        OscillatorType::Square,
-    ];
-    let wf_idx = (Math::random() * waves.len() as f64).floor() as usize;
-    oscillator.set_type(waves[wf_idx]);
-
-    oscillator.frequency().set_value(200.0);
-    let gain: GainNode = audio_ctx.create_gain()?;
-    gain.gain().set_value(0.0);
-
-    let filter: BiquadFilterNode = audio_ctx.create_biquad_filter()?; // ← new
-    filter.set_type(BiquadFilterType::Lowpass);
-    filter.frequency().set_value(1000.0);
-    filter.q().set_value(0.7);
-
-    // osc -> gain -> filter -> speakers
-    oscillator.connect_with_audio_node(&gain)?;
-    gain.connect_with_audio_node(&filter)?;
-    filter.connect_with_audio_node(&audio_ctx.destination())?;
-
-    oscillator.start()?;
-
-    // shared among events
-    let oscillator = Rc::new(oscillator);
-    let gain = Rc::new(gain);
-    let ctx = Rc::new(ctx);
-
-    let target = Rc::new(RefCell::new((w_css * 0.5, h_css * 0.5)));
-    let pos = Rc::new(RefCell::new((w_css * 0.5, h_css * 0.5)));
-
-    // last timestamp - used to calc dt for speed
-    let last_ts = Rc::new(RefCell::new(f64::NAN));
-
-    // generate user color (always some kind of pastel)
-    let hue = (Math::random() * 360.0).round(); // 0-360°
-    let color = format!("hsl({hue}, 70%, 70%)");
-    let color = Rc::new(color);
-
-    // WebSocket setup (production url: wss://femtanyl-systems.fly.dev/ws)
-    let ws = WebSocket::new("wss://femtanyl-systems.fly.dev/ws")?;
-    let ws = Rc::new(ws);
-    let peers_t = Rc::new(RefCell::new(HashMap::<String, Peer>::new()));
-    let peers_p = Rc::new(RefCell::new(HashMap::<String, Peer>::new()));
-    let trails = Rc::new(RefCell::new(HashMap::<String, VecDeque<TrailPoint>>::new()));
-    let my_trail = Rc::new(RefCell::new(VecDeque::<TrailPoint>::new()));
-    let my_id = Rc::new(RefCell::new(None::<String>));
+    // the waveform is drawn even without sound, so seeded runs pick the same colors
+    let mut rng = Rng::new(config.seed);
+    let audio = Audio::new(&config, rng.next())?;
+
+    // a reload within a few minutes carries on where the last page left off
+    let session = Session::load(&win, &config);
+
+    // user color: last pick, or some kind of pastel for first-timers (and seeded runs)
+    let stored = session
+        .as_ref()
+        .map(|s| s.color.clone())
+        .or_else(|| stored_color(&win))
+        .filter(|_| config.seed.is_none());
+    let color = stored.unwrap_or_else(|| {
+        let hue = (rng.next() * 360.0).round(); // 0-360°
+        config.color_style.color(hue, config.theme)
+    });
+
+    // color picker, top left
+    let picker: HtmlInputElement = document.create_element("input")?.dyn_into()?;
+    picker.set_type("color");
+    picker.set_value(&color);
+    picker.set_title("your color");
+    picker.style().set_css_text(
+        "position: fixed; left: 12px; top: 12px; width: 28px; height: 28px; padding: 0; border: none; background: none; cursor: pointer;",
+    );
+    document.body().unwrap().append_child(&picker)?;
+
+    // seek bar, only shown while replaying
+    let seek: HtmlInputElement = document.create_element("input")?.dyn_into()?;
+    seek.set_type("range");
+    seek.set_min("0");
+    seek.set_step("any");
+    seek.style().set_css_text(
+        "position: fixed; left: 5%; bottom: 16px; width: 90%; cursor: auto; display: none;",
+    );
+    document.body().unwrap().append_child(&seek)?;
+
+    let mut roster = Roster::new(&document)?;
+    roster.upsert(&document, Roster::YOU, &color);
+
+    // WebSocket setup
+    let ws = Rc::new(Socket::open(&color, &config)?);
 
     // Prevent flashbangs
-    clear_canvas(&ctx, w_css, h_css);
+    clear_canvas(&ctx, w_css, h_css, &config.background);
+
+    let pointer_filter = config
+        .smooth
+        .map(|(min_cutoff, beta)| OneEuro::new(min_cutoff, beta));
+    let state = Rc::new(RefCell::new(ClientState {
+        config,
+        ws: ws.clone(),
+        ctx,
+        document,
+        roster,
+        spotlight: Spotlight::default(),
+        audio,
+        w_css,
+        h_css,
+        color,
+        my_id: None,
+        target: (w_css * 0.5, h_css * 0.5),
+        pos: (w_css * 0.5, h_css * 0.5),
+        my_trail: VecDeque::new(),
+        my_history: VecDeque::new(),
+        my_path: VecDeque::new(),
+        rewind: None,
+        keys: HashSet::new(),
+        idle: 0.0,
+        fingers: HashMap::new(),
+        fingers_sent: f64::NEG_INFINITY,
+        pointer_filter,
+        unacked: VecDeque::new(),
+        peers_t: HashMap::new(),
+        peers_p: HashMap::new(),
+        trails: HashMap::new(),
+        arrivals: HashMap::new(),
+        voices: HashMap::new(),
+        jitter: HashMap::new(),
+        departed: HashSet::new(),
+        lifted: HashMap::new(),
+        grid: Grid::new(GRID_CELL),
+        view: (0.0, 0.0),
+        follow: None,
+        replay: None,
+        seek: seek.clone(),
+        status: None,
+        online: None,
+        room: None,
+        notice: None,
+        frozen: false,
+        marks: Vec::new(),
+        press: None,
+        clicked: None,
+        shake: 0.0,
+        seq: Default::default(),
+        rtt: VecDeque::new(),
+        budget: FrameBudget::new(),
+        debug: false,
+        last_ts: f64::NAN,
+        dirty: None,
+        audio_acc: 0.0,
+        ping_acc: 0.0,
+        last_heard: perf_now(),
+        send_every: 0.0,
+        last_send: f64::NEG_INFINITY,
+        send_pending: false,
+        last_saved: f64::NEG_INFINITY,
+    }));
+    if let Some(session) = session {
+        state.borrow_mut().resume(session, &win);
+    }
+    {
+        // save right away on reload or close; throttled saves cover crashes
+        let state = state.clone();
+        listen(&win, "pagehide", move |_: web_sys::Event| {
+            state.borrow().write_session();
+        })?;
+    }
+
+    // prefers-reduced-motion -> calmer board, following the setting as it changes
+    if let Some(query) = win.match_media("(prefers-reduced-motion: reduce)")? {
+        state.borrow_mut().config.reduce_motion(query.matches());
+        let state = state.clone();
+        listen(&query, "change", move |e: MediaQueryListEvent| {
+            state.borrow_mut().config.reduce_motion(e.matches());
+        })?;
+    }
 
     // onmessage -> update peers target
     {
-        let peers_t = peers_t.clone();
-        let my_id = my_id.clone();
-        let my_color = color.clone();
+        let state = state.clone();
         let cb = Closure::<dyn FnMut(MessageEvent)>::new(move |e: MessageEvent| {
-            if let Some(txt) = e.data().as_string() {
-                if let Ok(pkt) = serde_json::from_str::<Packet>(&txt) {
-                    // save my id
-                    if my_id.borrow().is_none() && pkt.color == *my_color {
-                        *my_id.borrow_mut() = Some(pkt.id.clone());
-                    }
-
-                    // store target for other players
-                    if Some(&pkt.id) != my_id.borrow().as_ref() {
-                        peers_t.borrow_mut().insert(
-                            pkt.id.clone(),
-                            Peer {
-                                x: pkt.x as f64 * w_css,
-                                y: pkt.y as f64 * h_css,
-                                color: pkt.color,
-                            },
-                        );
-                    }
-                }
+            let Some(txt) = e.data().as_string() else {
+                return;
+            };
+            let mut st = state.borrow_mut();
+            st.last_heard = perf_now();
+            st.ws.received.borrow_mut().add(st.last_heard, txt.len());
+            if let Some(stamped) = Stamped::decode(&txt) {
+                st.on_message(stamped);
             }
         });
-        ws.set_onmessage(Some(cb.as_ref().unchecked_ref()));
-        cb.forget();
+        *ws.on_message.borrow_mut() = Some(cb.into_js_value().unchecked_into());
     }
 
-    // onpointermove -> update target
+    // onopen -> reset backoff, narrow the subscription if asked to
     {
-        let ws = ws.clone();
-        let col = color.clone();
-        let target = target.clone();
-        let w_css = w_css;
-        let h_css = h_css;
-
-        let closure = Closure::<dyn FnMut(_)>::new(move |e: PointerEvent| {
-            let x = e.offset_x() as f64;
-            let y = e.offset_y() as f64;
-            *target.borrow_mut() = (x, y);
-
-            let pkt = Packet {
-                id: String::new(), // server fills in
-                color: col.clone().to_string(),
-                x: (x / w_css) as f32,
-                y: (y / h_css) as f32,
-                extra: HashMap::new(),
-            };
-            let _ = ws.send_with_str(&serde_json::to_string(&pkt).unwrap());
+        let state = state.clone();
+        let cb = Closure::<dyn FnMut()>::new(move || {
+            let mut st = state.borrow_mut();
+            st.ws.backoff.set(RECONNECT_MIN);
+            st.last_heard = perf_now();
+            st.status = None;
+            st.seq = Default::default();
+            if let Some(kinds) = st.config.kinds.clone() {
+                st.ws.send(&Msg::Subscribe { kinds });
+            }
         });
-        canvas.add_event_listener_with_callback("pointermove", closure.as_ref().unchecked_ref())?;
-        closure.forget();
+        *ws.on_open.borrow_mut() = Some(cb.into_js_value().unchecked_into());
     }
 
-    // onanimationframe -> animate movement
+    // onclose -> say why, and reconnect (with backoff) unless it won't help
     {
-        let ctx = ctx.clone();
-        let pos = pos.clone();
-        let tgt = target.clone();
-        let osc = oscillator.clone();
-        let gn = gain.clone();
-        let ts0 = last_ts.clone();
-        let win = web_sys::window().unwrap();
-        let win_cb = win.clone();
-
-        let peers_t = peers_t.clone();
-        let peers_p = peers_p.clone();
+        let state = state.clone();
+        let win = win.clone();
+        let cb = Closure::<dyn FnMut(_)>::new(move |e: CloseEvent| {
+            let reason = CloseReason::from_code(e.code());
+            let why = match e.reason() {
+                r if !r.is_empty() => r,
+                _ => reason
+                    .map_or("connection lost", CloseReason::as_str)
+                    .to_string(),
+            };
 
-        let f = Rc::new(RefCell::new(None::<Closure<dyn FnMut(f64)>>));
-        let g = f.clone();
+            let mut st = state.borrow_mut();
+            st.online = None;
+            if !reason.is_none_or(CloseReason::retry) {
+                st.status = Some(format!("disconnected: {why}"));
+                return;
+            }
+            st.status = Some(format!("disconnected: {why}, reconnecting…"));
 
-        *g.borrow_mut() = Some(Closure::wrap(Box::new(move |time: f64| {
-            // clear canvas
-            clear_canvas(&ctx, w_css, h_css);
+            let delay = st.ws.backoff.get();
+            st.ws.backoff.set((delay * 2).min(RECONNECT_MAX));
+            let ws_retry = st.ws.clone();
+            let retry = Closure::once_into_js(move || {
+                let _ = ws_retry.reconnect();
+            });
+            let _ = win.set_timeout_with_callback_and_timeout_and_arguments_0(
+                retry.unchecked_ref(),
+                delay,
+            );
+        });
+        *ws.on_close.borrow_mut() = Some(cb.into_js_value().unchecked_into());
+    }
+    ws.rewire();
 
-            let dt = if ts0.borrow().is_nan() {
-                0.0
+    // onpointermove -> update target, or that finger's if it isn't the first
+    {
+        let state = state.clone();
+        listen(&canvas, "pointermove", move |e: PointerEvent| {
+            let (x, y) = (e.offset_x() as f64, e.offset_y() as f64);
+            let mut st = state.borrow_mut();
+            if e.is_primary() {
+                st.on_pointer_move(x, y);
             } else {
-                time - *ts0.borrow()
-            };
-            *ts0.borrow_mut() = time;
-
-            let now = time;
-
-            // ─── animate peers movement ───
-            for (id, tgt) in peers_t.borrow().iter() {
-                let mut peers_p_ref = peers_p.borrow_mut();
-                let current = peers_p_ref.entry(id.clone()).or_insert(tgt.clone());
-
-                // interpolate
-                let dx = tgt.x - current.x;
-                let dy = tgt.y - current.y;
-                let dist = (dx * dx + dy * dy).sqrt();
-                if dist > STOP_RADIUS && dt > 0.0 {
-                    let speed = dist * SPEED_FACTOR;
-                    let step = (speed * dt).min(dist);
-                    current.x += dx / dist * step;
-                    current.y += dy / dist * step;
-                }
+                st.finger_moved(e.pointer_id(), x, y);
+            }
+        })?;
+    }
 
-                // draw
-                ctx.begin_path();
-                ctx.set_fill_style_str(&current.color);
-                ctx.arc(current.x, current.y, 20., 0., 2. * PI).unwrap();
-                ctx.fill();
-
-                // sound from speed
-                let speed = (current.x - tgt.x).abs() + (current.y - tgt.y).abs();
-                osc.frequency().set_value((200.0 + speed * 800.0) as f32);
-                gn.gain()
-                    .set_value((speed * 2.0).min(0.1).clamp(0.0, MAX_VOLUME) as f32);
-
-                {
-                    let mut tr = trails.borrow_mut();
-                    let q = tr.entry(id.clone()).or_default();
-                    q.push_back(TrailPoint {
-                        x: current.x,
-                        y: current.y,
-                        t: now,
-                    });
-
-                    // drop expired points
-                    while q.front().map_or(false, |p| now - p.t > TRAIL_LIFE) {
-                        q.pop_front();
-                    }
-                }
+    // onkeydown/onkeyup -> track held direction keys
+    {
+        let state = state.clone();
+        listen(&win, "keydown", move |e: KeyboardEvent| {
+            let mut st = state.borrow_mut();
+            // leave shortcuts (paste, reload, print) to the browser
+            if typing(&st.document) || e.ctrl_key() || e.meta_key() || e.alt_key() {
+                return;
+            }
+            if key_dir(&e.code()).is_some() {
+                e.prevent_default();
+                st.keys.insert(e.code());
+            } else if e.repeat() {
+                // the rest act once per press, not for as long as it's held
+            } else if e.code() == "Backquote" {
+                st.debug = !st.debug;
+            } else if e.code() == "KeyP" {
+                st.roster.toggle();
+            } else if e.code() == "KeyL" {
+                st.spotlight.on = !st.spotlight.on;
+            } else if e.code() == "KeyR" {
+                st.start_rewind();
+            } else if e.code() == "KeyV" {
+                st.export_path();
+            } else if e.code() == "KeyG" {
+                st.config.ghost = !st.config.ghost;
+            } else if e.code() == "KeyT" {
+                st.toggle_theme();
+            } else if e.code() == "Minus" {
+                st.resize_cursor(1.0 / SIZE_STEP);
+            } else if e.code() == "Equal" {
+                st.resize_cursor(SIZE_STEP);
+            }
+        })?;
+    }
+    {
+        let state = state.clone();
+        listen(&win, "keyup", move |e: KeyboardEvent| {
+            state.borrow_mut().keys.remove(&e.code());
+        })?;
+    }
+    {
+        // keyups are lost while the tab is unfocused
+        let state = state.clone();
+        listen(&win, "blur", move |_: web_sys::Event| {
+            state.borrow_mut().keys.clear();
+        })?;
+    }
 
-                // 2. draw trail (old → new, fading)
-                if let Some(q) = trails.borrow().get(id) {
-                    for p in q {
-                        let age = now - p.t;
-                        let alpha = 1.0 - age / TRAIL_LIFE; // 1 → 0
-                        ctx.set_global_alpha(alpha);
-                        ctx.begin_path();
-                        ctx.set_fill_style_str(&current.color);
-                        ctx.arc(p.x, p.y, 20.0 * alpha, 0.0, 2.0 * PI).unwrap();
-                        ctx.fill();
-                    }
-                    ctx.set_global_alpha(1.0); // reset!
-                }
+    // click a roster row -> follow that peer
+    {
+        let state = state.clone();
+        let panel = state.borrow().roster.panel.clone();
+        listen(&panel, "click", move |e: MouseEvent| {
+            let id = e
+                .target()
+                .and_then(|t| t.dyn_into::<web_sys::Element>().ok())
+                .and_then(|el| el.closest("[data-id]").ok().flatten())
+                .and_then(|row| row.get_attribute("data-id"));
+            if id.is_some() {
+                state.borrow_mut().follow = id;
             }
+        })?;
+    }
 
-            // ─── self movement ───
+    // color picked -> tell the server, remember for next time
+    {
+        let state = state.clone();
+        let picker_rx = picker.clone();
+        listen(&picker, "change", move |_: web_sys::Event| {
+            state.borrow_mut().set_color(picker_rx.value());
+        })?;
+    }
+
+    // onpointerdown/up without a drag -> drop a marker for everyone;
+    // any further pointers are cursors of their own while down
+    {
+        let state = state.clone();
+        listen(&canvas, "pointerdown", move |e: PointerEvent| {
+            let (x, y) = (e.offset_x() as f64, e.offset_y() as f64);
+            let mut st = state.borrow_mut();
+            if e.is_primary() {
+                st.press = Some((x, y));
+            } else {
+                st.finger_down(e.pointer_id(), x, y);
+            }
+        })?;
+    }
+    {
+        let state = state.clone();
+        listen(&canvas, "pointerup", move |e: PointerEvent| {
+            let mut st = state.borrow_mut();
+            if e.is_primary() {
+                st.on_pointer_up(e.offset_x() as f64, e.offset_y() as f64);
+            } else {
+                st.finger_up(e.pointer_id());
+            }
+        })?;
+    }
+    {
+        let state = state.clone();
+        listen(&canvas, "pointercancel", move |e: PointerEvent| {
+            let mut st = state.borrow_mut();
+            if e.is_primary() {
+                st.press = None;
+            } else {
+                st.finger_up(e.pointer_id());
+            }
+        })?;
+    }
 
-            // pos
-            let (tx, ty) = *tgt.borrow();
-            let (mut x, mut y) = *pos.borrow_mut();
+    // ondblclick on a peer -> follow them, any later click -> release
+    {
+        let state = state.clone();
+        listen(&canvas, "dblclick", move |e: MouseEvent| {
+            state
+                .borrow_mut()
+                .on_dblclick(e.offset_x() as f64, e.offset_y() as f64);
+        })?;
+    }
+    {
+        let state = state.clone();
+        listen(&canvas, "click", move |_: MouseEvent| {
+            state.borrow_mut().follow = None;
+        })?;
+    }
 
-            let dx = tx - x;
-            let dy = ty - y;
-            let dist = (dx * dx + dy * dy).sqrt();
+    // ondrop of an .ndjson recording -> replay it with a seek bar, Escape leaves
+    listen(&canvas, "dragover", |e: DragEvent| e.prevent_default())?;
+    {
+        let state = state.clone();
+        listen(&canvas, "drop", move |e: DragEvent| {
+            e.prevent_default();
+            let Some(file) = e
+                .data_transfer()
+                .and_then(|dt| dt.files())
+                .and_then(|f| f.get(0))
+            else {
+                return;
+            };
+            let Ok(reader) = FileReader::new() else {
+                return;
+            };
 
-            let mut speed = 0.0;
-            if dist > STOP_RADIUS && dt > 0.0 {
-                speed = dist * SPEED_FACTOR;
-                let step = (speed * dt).min(dist);
-                let nx = dx / dist;
-                let ny = dy / dist;
-                x += nx * step;
-                y += ny * step;
-                *pos.borrow_mut() = (x, y);
+            let state = state.clone();
+            let rd = reader.clone();
+            let onload = Closure::<dyn FnMut()>::new(move || {
+                let txt = rd.result().ok().and_then(|r| r.as_string());
+                if let Some(loaded) = txt.as_deref().and_then(Replay::parse) {
+                    state.borrow_mut().load_replay(loaded);
+                }
+            });
+            reader.set_onload(Some(onload.as_ref().unchecked_ref()));
+            onload.forget();
+            let _ = reader.read_as_text(&file);
+        })?;
+    }
+    {
+        // scrub: seek on input, hold playback while the thumb is held
+        let state = state.clone();
+        listen(&seek, "input", move |_: web_sys::Event| {
+            let mut st = state.borrow_mut();
+            let at = st.seek.value_as_number();
+            if let Some(r) = st.replay.as_mut() {
+                r.at = at;
             }
-
-            // record my own trail
-            let mut q = my_trail.borrow_mut();
-            q.push_back(TrailPoint { x, y, t: now });
-            while q.front().map_or(false, |p| now - p.t > TRAIL_LIFE) {
-                q.pop_front();
+        })?;
+    }
+    for (event, scrubbing) in [("pointerdown", true), ("pointerup", false)] {
+        let state = state.clone();
+        listen(&seek, event, move |_: PointerEvent| {
+            if let Some(r) = state.borrow_mut().replay.as_mut() {
+                r.scrubbing = scrubbing;
             }
-
-            // draw my trail
-            for p in q.iter() {
-                let age = now - p.t;
-                let alpha = 1.0 - age / TRAIL_LIFE;
-                ctx.set_global_alpha(alpha);
-                ctx.begin_path();
-                ctx.set_fill_style_str(&color);
-                ctx.arc(p.x, p.y, 20.0 * alpha, 0.0, 2.0 * PI).unwrap();
-                ctx.fill();
+        })?;
+    }
+    {
+        let state = state.clone();
+        listen(&win, "keydown", move |e: KeyboardEvent| {
+            if e.key() == "Escape" {
+                state.borrow_mut().close_replay();
             }
-            ctx.set_global_alpha(1.0);
+        })?;
+    }
 
-            // draw
-            ctx.begin_path();
-            ctx.set_fill_style_str(&color);
-            ctx.arc(x, y, 20., 0., 2. * PI).unwrap();
-            ctx.fill();
+    // onanimationframe -> animate movement
+    {
+        let win_cb = win.clone();
+        let f = Rc::new(RefCell::new(None::<Closure<dyn FnMut(f64)>>));
+        let g = f.clone();
 
-            // sound from speed
-            osc.frequency().set_value((200.0 + speed * 800.0) as f32);
-            gn.gain()
-                .set_value((speed * 2.0).min(0.1).clamp(0.0, MAX_VOLUME) as f32);
+        *g.borrow_mut() = Some(Closure::wrap(Box::new(move |time: f64| {
+            state.borrow_mut().frame(time);
 
             // schedule next frame
             win_cb
@@ -323,10 +3247,821 @@ pub fn run() -> Result<(), JsValue> {
         win.request_animation_frame(g.borrow().as_ref().unwrap().as_ref().unchecked_ref())?;
     }
 
-    return Ok(());
+    Ok(())
+}
+
+/// Attach `f` as a listener for `event`, for the lifetime of the page.
+fn listen<E>(
+    target: &web_sys::EventTarget,
+    event: &str,
+    f: impl FnMut(E) + 'static,
+) -> Result<(), JsValue>
+where
+    E: FromWasmAbi + 'static,
+{
+    let closure = Closure::<dyn FnMut(E)>::new(f);
+    target.add_event_listener_with_callback(event, closure.as_ref().unchecked_ref())?;
+    closure.forget();
+    Ok(())
+}
+
+/// Send `input` to the speakers through a convolver too, `mix` loud. The
+/// room is generated rather than bundled: decaying noise, different per ear.
+#[cfg(feature = "audio")]
+fn add_reverb(
+    ctx: &AudioContext,
+    input: &AudioNode,
+    mix: f64,
+    rng: &mut Rng,
+) -> Result<(), JsValue> {
+    let rate = ctx.sample_rate();
+    let room = ctx.create_buffer(2, (rate * REVERB_SECS) as u32, rate)?;
+    for channel in 0..2 {
+        room.copy_to_channel(&impulse(room.length() as usize, rng), channel)?;
+    }
+    let convolver = ctx.create_convolver()?;
+    convolver.set_buffer(Some(&room));
+    let wet = ctx.create_gain()?;
+    wet.gain().set_value(mix as f32);
+    input.connect_with_audio_node(&convolver)?;
+    convolver.connect_with_audio_node(&wet)?;
+    wet.connect_with_audio_node(&ctx.destination())?;
+    Ok(())
+}
+
+/// An impulse response `len` samples long: white noise fading out.
+#[cfg(feature = "audio")]
+fn impulse(len: usize, rng: &mut Rng) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let left = 1.0 - i as f64 / len as f64;
+            ((rng.next() * 2.0 - 1.0) * left.powf(REVERB_DECAY)) as f32
+        })
+        .collect()
+}
+
+/// Where we're heading, for everyone (normalized position).
+fn move_packet(color: &str, config: &Config, x: f64, y: f64) -> Packet {
+    let mut pkt = Packet {
+        id: String::new(), // server fills in
+        color: color.to_string(),
+        x: x as f32,
+        y: y as f32,
+        q: None,
+        rainbow: config.rainbow,
+        pointer: None,
+        lifted: false,
+        extra: HashMap::new(),
+    };
+    if let Some(bits) = config.precision {
+        pkt.quantize(bits);
+    }
+    pkt
+}
+
+/// A pointer down besides the primary one, in world space.
+struct Finger {
+    sub: u8, // its `Packet::pointer`
+    at: (f64, f64),
+    moved: bool, // since it last went out
+}
+
+/// Lowest `Packet::pointer` not among `taken`, if any is left.
+fn free_pointer(taken: impl Iterator<Item = u8> + Clone) -> Option<u8> {
+    (1..=Packet::MAX_POINTERS).find(|p| !taken.clone().any(|t| t == *p))
+}
+
+/// Cycling color for rainbow cursors, in our own style. Driven by the local
+/// clock, so peers needn't agree on the phase.
+fn rainbow_color(now: f64, config: &Config) -> String {
+    let hue = (now / RAINBOW_PERIOD * 360.0) % 360.0;
+    config.color_style.color(hue, config.theme)
+}
+
+/// Left stick of the first connected gamepad, deadzoned; `None` when centred
+/// or there's no pad.
+fn gamepad_stick() -> Option<(f64, f64)> {
+    let pads = web_sys::window()?.navigator().get_gamepads().ok()?;
+    let pad = pads
+        .iter()
+        .filter_map(|p| p.dyn_into::<Gamepad>().ok())
+        .find(Gamepad::connected)?;
+    let axes = pad.axes();
+    deadzone(axes.get(0).as_f64()?, axes.get(1).as_f64()?, PAD_DEADZONE)
+}
+
+/// Radial deadzone: travel under `dz` is dropped and the rest rescaled so
+/// output still spans 0..=1.
+fn deadzone(x: f64, y: f64, dz: f64) -> Option<(f64, f64)> {
+    let mag = (x * x + y * y).sqrt();
+    if mag <= dz {
+        return None;
+    }
+    let k = ((mag - dz) / (1.0 - dz)).min(1.0) / mag;
+    Some((x * k, y * k))
+}
+
+/// Unit direction for a movement key code, if it is one.
+fn key_dir(code: &str) -> Option<(f64, f64)> {
+    match code {
+        "ArrowUp" | "KeyW" => Some((0.0, -1.0)),
+        "ArrowDown" | "KeyS" => Some((0.0, 1.0)),
+        "ArrowLeft" | "KeyA" => Some((-1.0, 0.0)),
+        "ArrowRight" | "KeyD" => Some((1.0, 0.0)),
+        _ => None,
+    }
+}
+
+/// Douglas–Peucker: the fewest of `points` that stay within `tolerance`
+/// px of the line through all of them. Ends are always kept.
+fn simplify(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    (keep[0], keep[points.len() - 1]) = (true, true);
+    // spans still to check, as (first, last) indices; a stack rather than
+    // recursion, so long paths don't run out of it
+    let mut spans = vec![(0, points.len() - 1)];
+    while let Some((a, b)) = spans.pop() {
+        let far = (a + 1..b)
+            .map(|i| (i, off_line(points[i], points[a], points[b])))
+            .max_by(|x, y| x.1.total_cmp(&y.1));
+        if let Some((i, _)) = far.filter(|(_, dist)| *dist > tolerance) {
+            keep[i] = true;
+            spans.push((a, i));
+            spans.push((i, b));
+        }
+    }
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(p, kept)| kept.then_some(*p))
+        .collect()
+}
+
+/// Distance from `p` to the segment `a`–`b`.
+fn off_line(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len2 = dx * dx + dy * dy;
+    let k = match len2 {
+        0.0 => 0.0,
+        _ => (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len2).clamp(0.0, 1.0),
+    };
+    (p.0 - (a.0 + dx * k)).hypot(p.1 - (a.1 + dy * k))
+}
+
+/// A standalone SVG of `points` as one round-capped stroke `width` px wide,
+/// framed to fit. `None` with nothing to draw.
+fn svg_path(points: &[(f64, f64)], color: &str, width: f64) -> Option<String> {
+    let ((x0, y0), rest) = points.split_first()?;
+    let pad = width;
+    let (mut min, mut max) = ((*x0, *y0), (*x0, *y0));
+    for &(x, y) in rest {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    let (w, h) = (max.0 - min.0 + 2.0 * pad, max.1 - min.1 + 2.0 * pad);
+    let mut d = format!("M{:.1} {:.1}", x0 - min.0 + pad, y0 - min.1 + pad);
+    for (x, y) in rest {
+        d.push_str(&format!("L{:.1} {:.1}", x - min.0 + pad, y - min.1 + pad));
+    }
+    Some(format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.1} {h:.1}"><path d="{d}" fill="none" stroke="{color}" stroke-width="{width:.1}" stroke-linecap="round" stroke-linejoin="round"/></svg>"#
+    ))
+}
+
+/// Have the browser save `body` as a file called `name`.
+fn download(
+    document: &web_sys::Document,
+    name: &str,
+    mime: &str,
+    body: &str,
+) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&JsValue::from_str(body));
+    let options = BlobPropertyBag::new();
+    options.set_type(mime);
+    let blob = Blob::new_with_str_sequence_and_options(&parts, &options)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let link: HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    Url::revoke_object_url(&url)
+}
+
+/// Whether keyboard input currently belongs to a text field.
+fn typing(document: &web_sys::Document) -> bool {
+    document
+        .active_element()
+        .is_some_and(|el| matches!(el.tag_name().as_str(), "INPUT" | "TEXTAREA"))
+}
+
+/// Read a `?name=value` from the page url.
+fn query_param(win: &web_sys::Window, name: &str) -> Option<String> {
+    let search = win.location().search().ok()?;
+    web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get(name)
 }
 
-fn clear_canvas(ctx: &CanvasRenderingContext2d, win_width: f64, win_height: f64) {
-    ctx.set_fill_style_str("#121212");
+fn clear_canvas(ctx: &CanvasRenderingContext2d, win_width: f64, win_height: f64, bg: &str) {
+    ctx.set_fill_style_str(bg);
     ctx.fill_rect(0., 0., win_width, win_height);
 }
+
+/// Round trips over the last ~30 s as a line, scaled to the worst sample,
+/// with the latest value as a label. Returns the box it covered.
+fn draw_sparkline(ctx: &CanvasRenderingContext2d, samples: &VecDeque<f64>, x: f64, y: f64) -> Rect {
+    let peak = samples.iter().copied().fold(50.0, f64::max);
+    let step = SPARK_W / (RTT_SAMPLES - 1) as f64;
+
+    ctx.set_stroke_style_str("#888");
+    ctx.set_line_width(1.0);
+    ctx.begin_path();
+    for (i, ms) in samples.iter().enumerate() {
+        let px = x + i as f64 * step;
+        let py = y + SPARK_H - ms / peak * SPARK_H;
+        if i == 0 {
+            ctx.move_to(px, py);
+        } else {
+            ctx.line_to(px, py);
+        }
+    }
+    ctx.stroke();
+
+    let label = samples
+        .back()
+        .map_or("rtt —".to_string(), |ms| format!("rtt {ms:.0} ms"));
+    ctx.set_font("12px monospace");
+    ctx.set_fill_style_str("#888");
+    ctx.fill_text(&label, x, y + SPARK_H + 14.0).unwrap();
+
+    Rect {
+        x0: x,
+        y0: y,
+        x1: x + SPARK_W,
+        y1: y + SPARK_H + 18.0,
+    }
+}
+
+/// Cursor scale: `?size=` if given (and remembered), else the last one used.
+fn cursor_size(win: &web_sys::Window) -> f64 {
+    let storage = win.local_storage().ok().flatten();
+    let size = match query_param(win, "size").and_then(|s| s.parse::<f64>().ok()) {
+        Some(size) => {
+            if let Some(storage) = &storage {
+                let _ = storage.set_item(SIZE_KEY, &size.to_string());
+            }
+            size
+        }
+        None => storage
+            .and_then(|s| s.get_item(SIZE_KEY).ok().flatten())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1.0),
+    };
+    size.clamp(MIN_SIZE, MAX_SIZE)
+}
+
+/// The color picked on an earlier visit, if it's one the picker can show.
+fn stored_color(win: &web_sys::Window) -> Option<String> {
+    let color = win.local_storage().ok()??.get_item(COLOR_KEY).ok()??;
+    is_input_color(&color).then_some(color)
+}
+
+/// `#rrggbb`, the only form color inputs take.
+fn is_input_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// The `?room=` asked for, `None` for the default one.
+fn room_name(config: &Config) -> Option<&str> {
+    config
+        .room
+        .iter()
+        .find_map(|(k, v)| (*k == "room").then_some(v.as_str()))
+}
+
+/// What a reload picks up again, saved to localStorage every `SESSION_SAVE`.
+/// Color and size are remembered for good on their own; this is the live
+/// part, only worth resuming shortly after.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Session {
+    saved: f64,           // `Date.now()` ms
+    room: Option<String>, // positions only make sense on the same board
+    pos: (f64, f64),      // world px
+    view: (f64, f64),
+    color: String,
+    light: bool,
+    ghost: bool,
+    spotlight: bool,
+    debug: bool,
+}
+
+impl Session {
+    fn load(win: &web_sys::Window, config: &Config) -> Option<Self> {
+        let json = win.local_storage().ok()??.get_item(SESSION_KEY).ok()??;
+        Self::parse(&json, js_sys::Date::now(), room_name(config))
+    }
+
+    /// A saved session, if it's recent, for `room` and not garbled.
+    fn parse(json: &str, now: f64, room: Option<&str>) -> Option<Self> {
+        let session: Self = serde_json::from_str(json).ok()?;
+        let fresh = (0.0..SESSION_TTL).contains(&(now - session.saved));
+        let finite = [session.pos.0, session.pos.1, session.view.0, session.view.1]
+            .iter()
+            .all(|v| v.is_finite());
+        (fresh && finite && session.room.as_deref() == room && is_input_color(&session.color))
+            .then_some(session)
+    }
+}
+
+/// `#rrggbb` for a hue in degrees and saturation/lightness in 0..=1
+/// (color inputs only take hex).
+fn hsl_hex(h: f64, s: f64, l: f64) -> String {
+    let a = s * l.min(1.0 - l);
+    let channel = |n: f64| {
+        let k = (n + h / 30.0) % 12.0;
+        let v = l - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+        (v * 255.0).round() as u8
+    };
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(0.0),
+        channel(8.0),
+        channel(4.0)
+    )
+}
+
+/// High resolution time on the same clock as animation frame timestamps.
+fn perf_now() -> f64 {
+    web_sys::window()
+        .and_then(|w| w.performance())
+        .map_or(0.0, |p| p.now())
+}
+
+fn clear_rect(ctx: &CanvasRenderingContext2d, r: Rect, bg: &str) {
+    ctx.set_fill_style_str(bg);
+    ctx.fill_rect(
+        r.x0 - DIRTY_PAD,
+        r.y0 - DIRTY_PAD,
+        r.x1 - r.x0 + 2.0 * DIRTY_PAD,
+        r.y1 - r.y0 + 2.0 * DIRTY_PAD,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEED: f64 = 0.01;
+    const LIFE: f64 = 1_000.0;
+
+    fn arrivals(points: &[(f64, f64)]) -> VecDeque<TrailPoint> {
+        points
+            .iter()
+            .map(|&(t, x)| TrailPoint { x, y: 0.0, t })
+            .collect()
+    }
+
+    fn trail(ts: &[f64]) -> VecDeque<TrailPoint> {
+        ts.iter()
+            .map(|&t| TrailPoint { x: 0.0, y: 0.0, t })
+            .collect()
+    }
+
+    #[test]
+    fn advance_holds_still_without_time() {
+        assert_eq!(
+            advance((0.0, 0.0), (100.0, 0.0), 0.0, SPEED),
+            ((0.0, 0.0), 0.0)
+        );
+    }
+
+    #[test]
+    fn advance_at_target_is_idle() {
+        assert_eq!(
+            advance((5.0, 5.0), (5.0, 5.0), 16.0, SPEED),
+            ((5.0, 5.0), 0.0)
+        );
+    }
+
+    #[test]
+    fn advance_steps_toward_target() {
+        let ((x, y), speed) = advance((0.0, 0.0), (100.0, 0.0), 10.0, SPEED);
+        assert_eq!(speed, 1.0);
+        assert_eq!((x, y), (10.0, 0.0));
+    }
+
+    #[test]
+    fn advance_never_overshoots() {
+        let (pos, _) = advance((0.0, 0.0), (30.0, 40.0), 1e9, SPEED);
+        assert_eq!(pos, (30.0, 40.0));
+    }
+
+    #[test]
+    fn prune_trail_drops_only_expired_points() {
+        let mut q = trail(&[0.0, 500.0, 1_000.0, 1_500.0]);
+        prune_trail(&mut q, 2_000.0, LIFE);
+        let left: Vec<_> = q.iter().map(|p| p.t).collect();
+        assert_eq!(left, [1_000.0, 1_500.0]);
+    }
+
+    #[test]
+    fn prune_trail_empties_stale_trails() {
+        let mut q = trail(&[0.0, 1.0]);
+        prune_trail(&mut q, 1e9, LIFE);
+        assert!(q.is_empty());
+
+        prune_trail(&mut q, 0.0, LIFE);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn sample_arrivals_interpolates_between_neighbours() {
+        let buf = arrivals(&[(0.0, 0.0), (100.0, 10.0), (200.0, 30.0)]);
+        assert_eq!(sample_arrivals(&buf, 150.0), Some((20.0, 0.0)));
+        assert_eq!(sample_arrivals(&buf, 100.0), Some((10.0, 0.0)));
+    }
+
+    #[test]
+    fn sample_arrivals_holds_at_the_ends() {
+        let buf = arrivals(&[(100.0, 10.0), (200.0, 30.0)]);
+        assert_eq!(sample_arrivals(&buf, 0.0), Some((10.0, 0.0)));
+        assert_eq!(sample_arrivals(&buf, 500.0), Some((30.0, 0.0)));
+        assert_eq!(sample_arrivals(&VecDeque::new(), 0.0), None);
+    }
+
+    #[test]
+    fn prune_arrivals_keeps_the_bracketing_pair() {
+        let mut buf = arrivals(&[(0.0, 0.0), (100.0, 10.0), (200.0, 30.0)]);
+        prune_arrivals(&mut buf, 150.0);
+        assert_eq!(buf.len(), 2);
+        assert_eq!(sample_arrivals(&buf, 150.0), Some((20.0, 0.0)));
+    }
+
+    #[test]
+    fn hsl_hex_matches_css() {
+        assert_eq!(hsl_hex(0.0, 1.0, 0.5), "#ff0000");
+        assert_eq!(hsl_hex(120.0, 1.0, 0.5), "#00ff00");
+        assert_eq!(hsl_hex(240.0, 0.7, 0.7), "#7d7de8");
+    }
+
+    #[test]
+    fn deadzone_drops_drift_and_rescales() {
+        assert_eq!(deadzone(0.1, -0.05, 0.15), None);
+        assert_eq!(deadzone(0.0, 0.0, 0.15), None);
+
+        let (x, y) = deadzone(1.0, 0.0, 0.15).unwrap();
+        assert_eq!((x, y), (1.0, 0.0));
+
+        let (x, _) = deadzone(0.575, 0.0, 0.15).unwrap();
+        assert!((x - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn leader_is_the_most_active_above_the_floor() {
+        let mut activity = HashMap::new();
+        assert_eq!(leader(&activity), None);
+
+        activity.insert("a".to_string(), 1.0);
+        assert_eq!(leader(&activity), None);
+
+        activity.insert("b".to_string(), 40.0);
+        activity.insert("c".to_string(), 12.0);
+        assert_eq!(leader(&activity), Some("b"));
+    }
+
+    #[test]
+    fn capped_frames_render_at_the_target_rate() {
+        let every = 1_000.0 / 30.0;
+        // 60 Hz vsync with a little jitter: every other frame renders
+        assert!(!frame_due(16.7, every));
+        assert!(frame_due(33.2, every));
+        assert!(frame_due(16.7, 0.0));
+    }
+
+    #[test]
+    fn seeded_rng_repeats() {
+        let draw = |seed| {
+            let mut rng = Rng::new(Some(seed));
+            (0..100).map(|_| rng.next()).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+        assert!(draw(0).iter().all(|x| (0.0..1.0).contains(x)));
+    }
+
+    #[test]
+    fn smoothing_follows_with_the_time_constant() {
+        assert_eq!(smooth(0.0, 1.0, 16.0, 0.0), 1.0);
+        let after_tau = smooth(0.0, 1.0, 80.0, 80.0);
+        assert!((after_tau - (1.0 - (-1.0f64).exp())).abs() < 1e-9);
+        // same span in two steps lands in the same place
+        let stepped = smooth(smooth(0.0, 1.0, 40.0, 80.0), 1.0, 40.0, 80.0);
+        assert!((stepped - after_tau).abs() < 1e-9);
+    }
+
+    #[test]
+    fn light_backgrounds_are_spotted() {
+        assert_eq!(is_light("#fff"), Some(true));
+        assert_eq!(is_light("#fafafa"), Some(true));
+        assert_eq!(is_light("#121212"), Some(false));
+        assert_eq!(is_light("#00f"), Some(false));
+        assert_eq!(is_light("white"), None);
+        assert_eq!(is_light("#ggg"), None);
+    }
+
+    #[test]
+    fn byte_rate_forgets_old_samples() {
+        let mut rate = ByteRate::default();
+        rate.add(0.0, 5_000);
+        rate.add(4_000.0, 5_000);
+        assert_eq!(rate.per_sec(4_000.0), 2_000.0);
+        assert_eq!(rate.per_sec(6_000.0), 1_000.0);
+        assert_eq!(rate.per_sec(10_000.0), 0.0);
+        assert_eq!(fmt_rate(1_234.0), "1.2 kB/s");
+    }
+
+    #[test]
+    fn paths_simplify_to_their_corners() {
+        // an L drawn with a point every px, and a little wobble
+        let mut points: Vec<_> = (0..=100)
+            .map(|x| (x as f64, (x % 2) as f64 * 0.5))
+            .collect();
+        points.extend((1..=100).map(|y| (100.0, y as f64)));
+        let simple = simplify(&points, PATH_TOLERANCE);
+        assert_eq!(simple.len(), 3, "{simple:?}");
+        assert_eq!(simple[0], (0.0, 0.0));
+        assert_eq!(simple[2], (100.0, 100.0));
+        // tighter than the wobble keeps it
+        assert!(simplify(&points, 0.1).len() > 100);
+        assert_eq!(simplify(&points[..2], 1.0), points[..2]);
+        // a spike back onto itself isn't lost to its ends coinciding
+        let spike = [(0.0, 0.0), (50.0, 0.0), (0.0, 0.0)];
+        assert_eq!(simplify(&spike, 1.0).len(), 3);
+    }
+
+    #[test]
+    fn svg_paths_are_framed_around_the_stroke() {
+        assert_eq!(svg_path(&[], "#fff", 4.0), None);
+        let svg = svg_path(&[(10.0, 20.0), (30.0, 25.5)], "#ffaacc", 4.0).unwrap();
+        assert!(svg.starts_with("<svg "), "{svg}");
+        assert!(svg.contains(r#"viewBox="0 0 28.0 13.5""#), "{svg}");
+        assert!(svg.contains(r#"d="M4.0 4.0L24.0 9.5""#), "{svg}");
+        assert!(svg.contains(r##"stroke="#ffaacc""##), "{svg}");
+    }
+
+    #[test]
+    fn uneven_arrivals_read_as_laggy() {
+        let feed = |gaps: &[f64]| {
+            let mut j = Jitter::default();
+            let mut t = 0.0;
+            j.arrived(t);
+            for _ in 0..20 {
+                for g in gaps {
+                    t += g;
+                    j.arrived(t);
+                }
+            }
+            j.laggy()
+        };
+        assert!(feed(&[16.0]) < 0.01);
+        assert!(feed(&[5.0, 150.0]) > 0.9);
+        // resting between bursts of steady moves isn't lag
+        assert!(feed(&[16.0, 16.0, 16.0, 5_000.0]) < 0.01);
+        assert!(feed(&[16.0, 60.0]) > feed(&[16.0, 30.0]));
+    }
+
+    #[test]
+    fn desaturating_heads_for_grey() {
+        assert_eq!(desaturate("#ff0000", 0.0), "#ff0000");
+        assert_eq!(desaturate("#ff0000", 1.0), "#4c4c4c");
+        assert_eq!(desaturate("#f00", 0.5), "#a62626");
+        assert_eq!(desaturate("red", 1.0), "red");
+    }
+
+    #[test]
+    fn magnets_pull_push_and_fade_with_distance() {
+        let pull = |near: &[(f64, f64)], strength| {
+            magnet_force((0.0, 0.0), near.iter().copied(), strength, 100.0)
+        };
+        let (fx, fy) = pull(&[(50.0, 0.0)], 0.1);
+        assert!((fx - 0.05).abs() < 1e-9 && fy == 0.0);
+        let (fx, _) = pull(&[(50.0, 0.0)], -0.1);
+        assert!((fx + 0.05).abs() < 1e-9);
+        // closer pulls harder, out of reach or dead centre not at all
+        assert!(pull(&[(10.0, 0.0)], 0.1).0 > pull(&[(90.0, 0.0)], 0.1).0);
+        assert_eq!(pull(&[(100.0, 0.0), (0.0, 0.0)], 0.1), (0.0, 0.0));
+        // opposite peers cancel out
+        let (fx, fy) = pull(&[(0.0, 30.0), (0.0, -30.0)], 0.1);
+        assert!(fx.abs() < 1e-9 && fy.abs() < 1e-9);
+    }
+
+    #[test]
+    fn screensaver_loops_gently_within_reach() {
+        assert_eq!(saver_path(0.0), (0.0, 0.0));
+        let mut last = saver_path(0.0);
+        for i in 1..=60_000 {
+            let (x, y) = saver_path(i as f64 * 10.0);
+            assert!(x.abs() <= 1.0 && y.abs() <= 1.0);
+            // a 10 ms step never moves it far
+            assert!((x - last.0).hypot(y - last.1) < 0.01);
+            last = (x, y);
+        }
+    }
+
+    #[test]
+    fn sessions_resume_only_when_fresh_and_for_the_same_room() {
+        let session = Session {
+            saved: 1_000.0,
+            room: Some("jam".into()),
+            pos: (120.0, 80.0),
+            view: (0.0, -40.0),
+            color: "#ffaacc".into(),
+            light: true,
+            ghost: false,
+            spotlight: true,
+            debug: false,
+        };
+        let json = serde_json::to_string(&session).unwrap();
+        let soon = 1_000.0 + SESSION_TTL / 2.0;
+        assert_eq!(Session::parse(&json, soon, Some("jam")), Some(session));
+        assert!(Session::parse(&json, soon, None).is_none());
+        assert!(Session::parse(&json, 1_000.0 + SESSION_TTL, Some("jam")).is_none());
+        // a clock that went backwards isn't trusted either
+        assert!(Session::parse(&json, 0.0, Some("jam")).is_none());
+        assert!(Session::parse("{\"saved\":1000}", soon, Some("jam")).is_none());
+        let garbled = json.replace("#ffaacc", "red");
+        assert!(Session::parse(&garbled, soon, Some("jam")).is_none());
+    }
+
+    #[test]
+    fn color_styles() {
+        assert_eq!(ColorStyle::Pastel.color(240.0, Theme::Dark), "#7d7de8");
+        assert_eq!(ColorStyle::Vivid.color(0.0, Theme::Dark), "#ff0000");
+        let grey = ColorStyle::Grey.color(180.0, Theme::Dark);
+        assert_eq!(grey[1..3], grey[3..5]);
+        assert_eq!(grey[3..5], grey[5..7]);
+
+        let safe = |hue, theme| ColorStyle::Safe.color(hue, theme);
+        assert_eq!(safe(0.0, Theme::Dark), "#e69f00");
+        assert_eq!(safe(359.9, Theme::Dark), "#f0e442");
+        assert_eq!(safe(359.9, Theme::Light), "#000000");
+        let picks: HashSet<_> = (0..360).map(|h| safe(h as f64, Theme::Light)).collect();
+        assert_eq!(picks.len(), 7);
+        assert!(!picks.contains("#f0e442"));
+    }
+
+    #[test]
+    fn fingers_take_the_lowest_free_pointer() {
+        assert_eq!(free_pointer([].into_iter()), Some(1));
+        assert_eq!(free_pointer([1, 3].into_iter()), Some(2));
+        let all = 1..=Packet::MAX_POINTERS;
+        assert_eq!(free_pointer(all), None);
+        assert_eq!(cursor_owner("abc/2"), "abc");
+        assert_eq!(cursor_owner("abc"), "abc");
+    }
+
+    #[test]
+    fn echoes_acknowledge_or_override_predictions() {
+        let mut unacked = VecDeque::from([(0.0, 0.0), (10.0, 0.0), (20.0, 0.0)]);
+        // a coalesced echo of the middle move acks it and the one before
+        assert!(reconcile(&mut unacked, (11.0, 0.0), 2.0));
+        assert_eq!(unacked, [(20.0, 0.0)]);
+
+        // the server moved us somewhere we never asked for
+        assert!(!reconcile(&mut unacked, (5.0, 5.0), 2.0));
+        assert!(unacked.is_empty());
+    }
+
+    #[test]
+    fn trails_go_to_the_busiest_peers() {
+        let voices = HashMap::from([("a".to_string(), 0.1), ("b".into(), 3.0), ("c".into(), 1.0)]);
+        assert_eq!(busiest(&voices, 2), HashSet::from(["b".into(), "c".into()]));
+        assert_eq!(busiest(&voices, 10).len(), 3);
+        assert!(busiest(&voices, 0).is_empty());
+    }
+
+    #[test]
+    fn slow_frames_shed_detail_until_they_recover() {
+        let mut budget = FrameBudget::new();
+        let mut t = 0.0;
+        let mut run = |budget: &mut FrameBudget, took, ms| {
+            let end = t + ms;
+            while t < end {
+                budget.record(took, t, 16.0);
+                t += 16.0;
+            }
+        };
+
+        run(&mut budget, 30.0, 1_000.0);
+        assert_eq!(budget.quality, Quality::Medium);
+        // held there a while before dropping again
+        run(&mut budget, 30.0, 1_000.0);
+        assert_eq!(budget.quality, Quality::Medium);
+        run(&mut budget, 30.0, 2_000.0);
+        assert_eq!(budget.quality, Quality::Low);
+
+        // within budget but not comfortably: stays put
+        run(&mut budget, 12.0, 5_000.0);
+        assert_eq!(budget.quality, Quality::Low);
+        run(&mut budget, 2.0, 5_000.0);
+        assert_eq!(budget.quality, Quality::High);
+    }
+
+    #[test]
+    fn bloom_is_shed_below_full_quality() {
+        assert_eq!(Quality::High.glow(0.0, GLOW_BLUR / 2.0), 0.5);
+        assert_eq!(Quality::High.glow(0.8, GLOW_BLUR / 2.0), 0.8);
+        assert_eq!(Quality::Medium.glow(0.8, GLOW_BLUR), 0.0);
+    }
+
+    #[test]
+    fn no_budget_keeps_full_quality() {
+        let mut budget = FrameBudget::new();
+        for i in 0..1_000 {
+            budget.record(100.0, i as f64 * 16.0, 0.0);
+        }
+        assert_eq!(budget.quality, Quality::High);
+    }
+
+    #[test]
+    #[cfg(feature = "audio")]
+    fn reverb_impulse_dies_away() {
+        let ir = impulse(10_000, &mut Rng::new(Some(7)));
+        let energy = |s: &[f32]| s.iter().map(|v| v * v).sum::<f32>();
+        assert!(ir.iter().all(|v| v.abs() <= 1.0));
+        assert!(energy(&ir[..1_000]) > 100.0 * energy(&ir[9_000..]));
+    }
+
+    #[test]
+    fn one_euro_steadies_jitter_but_keeps_up_with_flicks() {
+        let mut filter = OneEuro::new(1.0, 0.01);
+        let mut rng = Rng::new(Some(3));
+        // resting hand: ±2 px of noise around (100, 100) at 120 Hz
+        let mut worst: f64 = 0.0;
+        for i in 0..240 {
+            let jitter = (rng.next() - 0.5) * 4.0;
+            let (x, _) = filter.filter(i as f64 * 8.0, (100.0 + jitter, 100.0));
+            if i > 120 {
+                worst = worst.max((x - 100.0).abs());
+            }
+        }
+        assert!(worst < 1.0, "jitter got through: {worst}");
+
+        // a flick 500 px away is most of the way there within a few samples
+        let mut x = 100.0;
+        for i in 240..246 {
+            x = filter.filter(i as f64 * 8.0, (600.0, 100.0)).0;
+        }
+        assert!(x > 500.0, "lagging at {x}");
+    }
+
+    #[test]
+    fn peers_are_told_apart_by_the_welcomed_id() {
+        let txt = r##"{"kind":"welcome","id":"Ab3dE9","color":"#ffaacc"}"##;
+        let Some(Msg::Welcome(welcome)) = Msg::decode(txt) else {
+            panic!("not a welcome");
+        };
+        let my_id = welcome.id.as_deref();
+        assert!(!is_peer(my_id, "Ab3dE9"));
+        assert!(is_peer(my_id, "Zz0000"));
+        // before it arrives, everyone is
+        assert!(is_peer(None, "Ab3dE9"));
+    }
+
+    #[test]
+    fn grid_finds_only_whoever_is_in_range() {
+        let mut grid = Grid::new(10.0);
+        grid.insert("a", 5.0, 5.0);
+        grid.insert("b", 14.0, 5.0); // next cell over, still close
+        grid.insert("c", 100.0, 100.0);
+        grid.insert("d", -3.0, -3.0); // negative coordinates bucket too
+
+        let mut near: Vec<_> = grid.within(6.0, 5.0, 9.0).collect();
+        near.sort();
+        assert_eq!(near, ["a", "b"]);
+        assert_eq!(grid.within(-2.0, -2.0, 2.0).collect::<Vec<_>>(), ["d"]);
+        assert_eq!(grid.within(50.0, 50.0, 5.0).count(), 0);
+
+        grid.clear();
+        assert_eq!(grid.within(5.0, 5.0, 100.0).count(), 0);
+    }
+
+    #[test]
+    fn rewind_plays_back_a_moving_window() {
+        let rewind = Rewind {
+            points: trail(&[0.0, 100.0, 200.0, 300.0, 400.0]).into(),
+            started: 0.0,
+        };
+        assert_eq!(rewind.duration(), 400.0);
+        let times =
+            |at, life| -> Vec<f64> { rewind.showing(at, life).iter().map(|p| p.t).collect() };
+        assert_eq!(times(150.0, 1_000.0), [0.0, 100.0]);
+        assert_eq!(times(350.0, 200.0), [200.0, 300.0]);
+        assert!(times(900.0, 200.0).is_empty());
+    }
+}
@@ -0,0 +1,2678 @@
+//! Cursor relay: rooms of websocket (or WebTransport) clients fanning
+//! messages out to each other. The `server` binary serves [`router`] on its
+//! own; embed it in a larger axum app the same way.
+
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocketUpgrade},
+        ConnectInfo, Extension, Query,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use shared::{
+    cursor_owner, is_hex_color, CloseReason, Cursor, Kind, Msg, Packet, PeerState, RoomSummary,
+    Stamped, Tunables, Welcome,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::{sync::Arc, time::Instant};
+use tokio::sync::{broadcast, broadcast::error::RecvError, mpsc, oneshot, watch};
+use tokio::{sync::Mutex, time::Duration};
+use uuid::Uuid;
+
+mod store;
+mod webtransport;
+use store::Store;
+
+/// How long a close frame gets to flush before the connection is dropped.
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// Board a connection joins when it doesn't ask for one (`/ws?room=..`).
+const DEFAULT_ROOM: &str = "main";
+
+/// Wire versions accepted on connect. 0 covers builds from before clients
+/// sent one; raise the floor once those have aged out of browser caches.
+const VERSIONS: std::ops::RangeInclusive<u8> = 0..=shared::VERSION;
+
+/// Minimum time between color changes for one connection.
+const RECOLOR_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Minimum time between markers from one connection; extras are dropped.
+const MARK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Chat allowance: `CHAT_BURST` messages per `CHAT_WINDOW`, each at most
+/// `CHAT_MAX_LEN` chars. Hitting the limit `CHAT_STRIKES` times running
+/// mutes the connection for `CHAT_MUTE`.
+const CHAT_BURST: usize = 5;
+const CHAT_WINDOW: Duration = Duration::from_secs(10);
+const CHAT_MAX_LEN: usize = 280;
+const CHAT_STRIKES: u32 = 3;
+const CHAT_MUTE: Duration = Duration::from_secs(60);
+
+/// Move allowance, per pointer per second, as a multiple of the room's tick
+/// rate. Clients send at most one a tick, so only a flood gets near it;
+/// moves over it are dropped.
+const MOVE_SLACK: u32 = 4;
+
+/// Flood control over all of the above: `FLOOD_STRIKES` limit hits within
+/// `FLOOD_WINDOW` stop the connection being read for `FLOOD_PAUSE`, doubling
+/// each time up to `FLOOD_MAX_PAUSE`. `FLOOD_COOLDOWN` of good behaviour
+/// starts it over.
+const FLOOD_STRIKES: usize = 10;
+const FLOOD_WINDOW: Duration = Duration::from_secs(10);
+const FLOOD_PAUSE: Duration = Duration::from_secs(1);
+const FLOOD_MAX_PAUSE: Duration = Duration::from_secs(30);
+const FLOOD_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Server settings, read once from the environment.
+pub struct Config {
+    pub port: u16,
+    /// lets a connection that presents it (`/ws?token=..`) moderate
+    pub admin_token: Option<String>,
+    /// take the client address from Fly's forwarding headers
+    pub trust_proxy: bool,
+    /// sqlite file strokes are kept in, in memory when unset
+    pub db_path: Option<String>,
+    /// open connections allowed from one address
+    pub max_per_ip: usize,
+    /// fastest a cursor may travel, in board widths per second; off when unset
+    pub max_speed: Option<f32>,
+    /// what a new room runs with unless its creator asks otherwise
+    pub room: RoomSettings,
+    /// how long a room may sit empty before it's dropped
+    pub room_grace: Duration,
+    /// how long a connection may stay silent before it's dropped
+    pub idle_timeout: Duration,
+    /// relay only each sender's latest move once per room tick, rather than every one
+    pub coalesce: bool,
+    /// what unknown fields on moves may carry through to everyone else
+    pub extra: ExtraPolicy,
+    /// recent positions kept per cursor, to show joiners its trail; 0 sends positions only
+    pub snapshot_trail: usize,
+    /// moves still waiting to go out to a connection this long after they
+    /// were broadcast are skipped for fresher ones; off when unset
+    pub stale_after: Option<Duration>,
+    /// WebTransport endpoint served next to `/ws`; off when unset
+    pub webtransport: Option<WebTransport>,
+    /// handed to clients at `/config`
+    pub tunables: Tunables,
+}
+
+/// Where the WebTransport endpoint listens (UDP), and the PEM certificate
+/// chain and key it presents. Browsers check it like any other https one.
+pub struct WebTransport {
+    pub addr: SocketAddr,
+    pub cert: String,
+    pub key: String,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let room = RoomSettings::default();
+        Self {
+            // Use Fly.io's injected $PORT if available, otherwise default to 3000 for local runs.
+            port: env("PORT").unwrap_or(3000),
+            admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            trust_proxy: env("TRUST_PROXY").unwrap_or(true),
+            db_path: std::env::var("DB_PATH").ok().filter(|p| !p.is_empty()),
+            max_per_ip: env("MAX_PER_IP").unwrap_or(16),
+            max_speed: env("MAX_SPEED"),
+            room: RoomSettings {
+                max_peers: env("MAX_PEERS").unwrap_or(room.max_peers),
+                tick_rate: env("TICK_RATE").unwrap_or(room.tick_rate),
+                persist: env("PERSIST").unwrap_or(room.persist),
+            }
+            .clamped(),
+            room_grace: Duration::from_secs(env("ROOM_GRACE").unwrap_or(300)),
+            idle_timeout: Duration::from_secs(env("IDLE_TIMEOUT").unwrap_or(300)),
+            coalesce: env("COALESCE").unwrap_or(true),
+            extra: std::env::var("EXTRA")
+                .ok()
+                .and_then(|e| ExtraPolicy::parse(&e))
+                .unwrap_or_default(),
+            snapshot_trail: env("SNAPSHOT_TRAIL").unwrap_or(16),
+            stale_after: Some(env("STALE_AFTER").unwrap_or(2_000))
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+            webtransport: env("WT_PORT").and_then(|port: u16| {
+                Some(WebTransport {
+                    addr: SocketAddr::from(([0, 0, 0, 0], port)),
+                    cert: std::env::var("WT_CERT").ok()?,
+                    key: std::env::var("WT_KEY").ok()?,
+                })
+            }),
+            tunables: tunables_from_env(),
+        }
+    }
+}
+
+/// What's relayed of the fields a move carries beyond those the server
+/// knows (`Packet::extra`). They reach every peer, so by default only a
+/// little is let through.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExtraPolicy {
+    /// drop them all (`EXTRA=strip`)
+    Strip,
+    /// keep only these keys (`EXTRA=allow:trail,emoji`)
+    Allow(HashSet<String>),
+    /// keep them while they serialize to at most this many bytes, else drop
+    /// them all (`EXTRA=256`)
+    Cap(usize),
+}
+
+impl Default for ExtraPolicy {
+    fn default() -> Self {
+        ExtraPolicy::Cap(256)
+    }
+}
+
+impl ExtraPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "strip" => Some(ExtraPolicy::Strip),
+            s => match s.strip_prefix("allow:") {
+                Some(keys) => Some(ExtraPolicy::Allow(
+                    keys.split(',')
+                        .map(str::trim)
+                        .filter(|k| !k.is_empty())
+                        .map(String::from)
+                        .collect(),
+                )),
+                None => s.parse().ok().map(ExtraPolicy::Cap),
+            },
+        }
+    }
+
+    /// Cut `extra` down to what may be relayed, returning whether anything went.
+    fn apply(&self, extra: &mut HashMap<String, serde_json::Value>) -> bool {
+        let before = extra.len();
+        match self {
+            ExtraPolicy::Strip => extra.clear(),
+            ExtraPolicy::Allow(keys) => extra.retain(|k, _| keys.contains(k)),
+            ExtraPolicy::Cap(max) => {
+                let size = serde_json::to_vec(extra).map_or(usize::MAX, |v| v.len());
+                if !extra.is_empty() && size > *max {
+                    extra.clear();
+                }
+            }
+        }
+        extra.len() < before
+    }
+}
+
+fn tunables_from_env() -> Tunables {
+    let d = Tunables::default();
+    Tunables {
+        trail_life: env("TRAIL_LIFE").unwrap_or(d.trail_life),
+        speed_factor: env("SPEED_FACTOR").unwrap_or(d.speed_factor),
+        key_speed: env("KEY_SPEED").unwrap_or(d.key_speed),
+        pad_speed: env("PAD_SPEED").unwrap_or(d.pad_speed),
+        volume: env("VOLUME").unwrap_or(d.volume),
+        webtransport: std::env::var("WT_URL").ok().filter(|u| !u.is_empty()),
+    }
+}
+
+fn env<T: std::str::FromStr>(key: &str) -> Option<T> {
+    std::env::var(key).ok().and_then(|v| v.parse().ok())
+}
+
+/// Banned addresses and session ids. Seeded from `BANNED` (comma separated),
+/// grown by moderators at runtime; live sessions are told through `notify`.
+struct Bans {
+    set: std::sync::Mutex<HashSet<String>>,
+    notify: broadcast::Sender<String>,
+}
+
+impl Bans {
+    fn from_env() -> Self {
+        let set = std::env::var("BANNED")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+            .map(String::from)
+            .collect();
+        let (notify, _) = broadcast::channel(16);
+        Self {
+            set: std::sync::Mutex::new(set),
+            notify,
+        }
+    }
+
+    fn contains(&self, target: &str) -> bool {
+        self.set.lock().unwrap().contains(target)
+    }
+
+    fn add(&self, target: String) {
+        self.set.lock().unwrap().insert(target.clone());
+        let _ = self.notify.send(target);
+    }
+}
+
+/// Ids of live sessions. Peers are keyed by id on every client, so two
+/// concurrent connections must never share one. Each holds the way to
+/// kick it, once its connection is listening for that.
+#[derive(Default)]
+struct Ids(std::sync::Mutex<HashMap<String, Option<oneshot::Sender<()>>>>);
+
+impl Ids {
+    /// Claim an id from `generate` that no live session holds.
+    fn reserve(&self, mut generate: impl FnMut() -> String) -> String {
+        let mut live = self.0.lock().unwrap();
+        loop {
+            let id = generate();
+            if let std::collections::hash_map::Entry::Vacant(e) = live.entry(id.clone()) {
+                e.insert(None);
+                return id;
+            }
+            tracing::warn!(%id, "session id collision, regenerating");
+        }
+    }
+
+    fn release(&self, id: &str) {
+        self.0.lock().unwrap().remove(id);
+    }
+
+    /// Resolves when a moderator kicks `id`.
+    fn kicked(&self, id: &str) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        if let Some(slot) = self.0.lock().unwrap().get_mut(id) {
+            *slot = Some(tx);
+        }
+        rx
+    }
+
+    /// Disconnect `id`, returning whether it was live.
+    fn kick(&self, id: &str) -> bool {
+        let tx = self.0.lock().unwrap().get_mut(id).and_then(Option::take);
+        tx.is_some_and(|tx| tx.send(()).is_ok())
+    }
+}
+
+/// How long a dropped connection's session is held for it to come back
+/// with its resume token, before peers are told it left.
+const RESUME_GRACE: Duration = Duration::from_secs(15);
+
+/// A session whose connection dropped, waiting to be picked up again.
+struct Parked {
+    id: String,
+    color: Option<String>,
+    room: String,
+    at: Instant,
+    /// kicked or banned while away: not to come back, but still to be
+    /// announced gone once the grace is up
+    revoked: bool,
+}
+
+/// Dropped sessions by resume token. Each token is good for one reconnect;
+/// the new connection is welcomed with a fresh one.
+#[derive(Default)]
+struct Resumes(std::sync::Mutex<HashMap<String, Parked>>);
+
+impl Resumes {
+    fn park(&self, token: String, parked: Parked) {
+        self.0.lock().unwrap().insert(token, parked);
+    }
+
+    /// Claim the session parked under `token`, if it was in `room` and
+    /// hasn't outstayed `RESUME_GRACE`.
+    fn take(&self, token: &str, room: &str) -> Option<Parked> {
+        let mut parked = self.0.lock().unwrap();
+        let p = parked.get(token)?;
+        if p.revoked || p.room != room || p.at.elapsed() >= RESUME_GRACE {
+            return None;
+        }
+        parked.remove(token)
+    }
+
+    /// Void any token parked for session `id`, returning whether there was one.
+    fn revoke(&self, id: &str) -> bool {
+        let mut parked = self.0.lock().unwrap();
+        let mut found = false;
+        for p in parked.values_mut().filter(|p| p.id == id) {
+            p.revoked = true;
+            found = true;
+        }
+        found
+    }
+
+    /// Give up on `token`, returning its session if nobody claimed it.
+    fn expire(&self, token: &str) -> Option<Parked> {
+        self.0.lock().unwrap().remove(token)
+    }
+}
+
+/// Length of a session id. Ids ride in every broadcast, so they are kept
+/// short: 62^6 is plenty for the connections alive at any one time, and
+/// `Ids::reserve` covers the rare repeat.
+const ID_LEN: usize = 6;
+
+/// A random base62 session id.
+fn new_id() -> String {
+    const ALPHABET: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut bits = Uuid::new_v4().as_u128();
+    (0..ID_LEN)
+        .map(|_| {
+            let c = ALPHABET[(bits % 62) as usize] as char;
+            bits /= 62;
+            c
+        })
+        .collect()
+}
+
+/// Open connections per client address.
+#[derive(Default)]
+struct PerIp(std::sync::Mutex<HashMap<IpAddr, usize>>);
+
+impl PerIp {
+    /// Count a connection from `ip`, unless it already has `cap` open.
+    fn acquire(self: &Arc<Self>, ip: IpAddr, cap: usize) -> Option<IpSlot> {
+        let mut open = self.0.lock().unwrap();
+        let n = open.entry(ip).or_default();
+        if *n >= cap {
+            return None;
+        }
+        *n += 1;
+        Some(IpSlot {
+            per_ip: self.clone(),
+            ip,
+        })
+    }
+}
+
+/// Held for the life of a connection; frees its slot however that ends
+/// (including an upgrade that never completes).
+struct IpSlot {
+    per_ip: Arc<PerIp>,
+    ip: IpAddr,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut open = self.per_ip.0.lock().unwrap();
+        if let Some(n) = open.get_mut(&self.ip) {
+            *n -= 1;
+            if *n == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// A serialized broadcast, tagged with its kind so subscribers can filter.
+/// Server announcements carry no kind and reach everyone.
+#[derive(Clone, Debug)]
+struct Item {
+    kind: Option<Kind>,
+    txt: String,
+    /// when it was broadcast, to spot ones a slow connection sat on
+    at: Instant,
+    /// a move the next one supersedes, so it can be skipped: sent on `lo`
+    lossy: bool,
+    /// for moves, the `Packet::cursor_key` they move
+    cursor: Option<String>,
+}
+
+impl Item {
+    /// Whether it's a move that has waited longer than `ttl` to be sent.
+    /// Only those go stale: a newer one supersedes them, where a chat,
+    /// stroke or a pointer's lift would just be lost.
+    fn stale(&self, ttl: Option<Duration>) -> bool {
+        self.lossy && ttl.is_some_and(|ttl| self.at.elapsed() > ttl)
+    }
+}
+
+/// Fan-out channels. Moves are best-effort and may be dropped under load,
+/// everything else, lifts included, goes through `hi` so it isn't starved or
+/// evicted by moves.
+#[derive(Clone)]
+struct Hub {
+    hi: broadcast::Sender<Item>,
+    lo: broadcast::Sender<Item>,
+    /// stamped on every broadcast, so clients can detect gaps: one per lane,
+    /// `hi` then `lo`, since `hi` is sent ahead of moves already queued
+    seqs: Arc<[AtomicU64; 2]>,
+    /// live connections, and ones with a `Seat` that are still upgrading
+    online: Arc<AtomicUsize>,
+}
+
+/// A place in a room, counted in `Hub::online` from before the upgrade so
+/// joiners racing for the last one can't both get it. Frees it on drop.
+struct Seat(Arc<AtomicUsize>);
+
+impl Drop for Seat {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Hub {
+    fn new(capacity: usize) -> Self {
+        let (hi, _) = broadcast::channel(capacity);
+        let (lo, _) = broadcast::channel(capacity);
+        Self {
+            hi,
+            lo,
+            seqs: Arc::default(),
+            online: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Stamp, serialize and fan out a relayed message.
+    fn send(&self, msg: Msg) {
+        let Some(kind) = msg.kind() else { return };
+        // nothing comes after a lift to put the cursor away if it were lost
+        let lossy = matches!(&msg, Msg::Move(pkt) if !pkt.lifted);
+        let cursor = match &msg {
+            Msg::Move(pkt) => Some(pkt.cursor_key()),
+            _ => None,
+        };
+        self.publish(lossy, Some(kind), cursor, msg);
+    }
+
+    /// Broadcast a server message to every connection, regardless of subscriptions.
+    fn announce(&self, msg: Msg) {
+        self.publish(false, None, None, msg);
+    }
+
+    /// Take a seat, unless `max` are already taken.
+    fn seat(&self, max: usize) -> Option<Seat> {
+        self.online
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(Seat(self.online.clone()))
+    }
+
+    /// Tell everyone how many are online, after a seat is taken or freed.
+    fn presence(&self) {
+        let online = self.online.load(Ordering::Relaxed);
+        self.announce(Msg::Presence { online });
+    }
+
+    fn publish(&self, lossy: bool, kind: Option<Kind>, cursor: Option<String>, msg: Msg) {
+        let seq = self.seqs[lossy as usize].fetch_add(1, Ordering::Relaxed);
+        let lift = matches!(&msg, Msg::Move(pkt) if pkt.lifted);
+        let Some(txt) = encode(&Stamped {
+            seq: Some(seq),
+            lossy,
+            supersedes: lift.then(|| self.seqs[1].load(Ordering::Relaxed)),
+            msg,
+        }) else {
+            return;
+        };
+        let channel = if lossy { &self.lo } else { &self.hi };
+        let _ = channel.send(Item {
+            kind,
+            txt,
+            at: Instant::now(),
+            lossy,
+            cursor,
+        });
+    }
+}
+
+/// Limits a room runs with. Its first connection (or a moderator's, later)
+/// picks them with `/ws?room=..&max_peers=..&tick=..&persist=0`; anything
+/// left out keeps the server default. Joiners are told them on connect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RoomSettings {
+    /// connections allowed at once
+    pub max_peers: usize,
+    /// most moves per second clients are asked to send
+    pub tick_rate: u32,
+    /// keep strokes in the store and replay them to joiners
+    pub persist: bool,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        Self {
+            max_peers: 256,
+            tick_rate: 60,
+            persist: true,
+        }
+    }
+}
+
+impl RoomSettings {
+    pub const MAX_PEERS: usize = 1_024;
+    pub const MAX_TICK_RATE: u32 = 120;
+
+    /// Pull everything into the range the server supports.
+    pub fn clamped(self) -> Self {
+        Self {
+            max_peers: self.max_peers.clamp(1, Self::MAX_PEERS),
+            tick_rate: self.tick_rate.clamp(1, Self::MAX_TICK_RATE),
+            persist: self.persist,
+        }
+    }
+
+    /// These settings with any asked for in `params` applied.
+    fn with(self, params: &HashMap<String, String>) -> Self {
+        Self {
+            max_peers: param(params, "max_peers").unwrap_or(self.max_peers),
+            tick_rate: param(params, "tick").unwrap_or(self.tick_rate),
+            persist: params
+                .get("persist")
+                .map_or(self.persist, |p| p != "0" && p != "false"),
+        }
+        .clamped()
+    }
+}
+
+fn param<T: std::str::FromStr>(params: &HashMap<String, String>, key: &str) -> Option<T> {
+    params.get(key).and_then(|v| v.parse().ok())
+}
+
+/// A board and everyone on it.
+#[derive(Clone)]
+struct Room {
+    name: String,
+    hub: Hub,
+    settings: RoomSettings,
+    /// where everyone's cursor last was, for `/wall` and joiners
+    cursors: Arc<std::sync::Mutex<HashMap<String, Cursor>>>,
+    /// and where it was before that, for joiners' snapshots
+    trails: Arc<std::sync::Mutex<HashMap<String, Trail>>>,
+    /// when the room was created or someone last left it
+    last_left: Arc<std::sync::Mutex<Instant>>,
+    /// latest move per sender since the last tick, when coalescing
+    moves: Arc<std::sync::Mutex<HashMap<String, Packet>>>,
+    /// whether `flush_moves` is running for this room
+    flushing: Arc<AtomicBool>,
+    /// `settings.tick_rate` as of the last moderator, for `flush_moves`
+    tick_rate: Arc<AtomicU32>,
+    /// moves aren't relayed while a moderator has the room paused
+    frozen: Arc<AtomicBool>,
+}
+
+/// A cursor's recent positions, oldest first, at most `Config::snapshot_trail`.
+type Trail = VecDeque<(f32, f32, Instant)>;
+
+impl Room {
+    /// Most cursors a room shows on the wall.
+    const WALL_CURSORS: usize = 32;
+
+    /// Most trail points in one snapshot, shared out between its cursors.
+    const SNAPSHOT_POINTS: usize = 2_048;
+
+    /// Everyone on the board, with up to `per_peer` trail points each no
+    /// older than `life`.
+    fn snapshot(&self, per_peer: usize, life: Duration) -> Vec<PeerState> {
+        let cursors = self.cursors.lock().unwrap();
+        let trails = self.trails.lock().unwrap();
+        let per_peer = per_peer.min(Self::SNAPSHOT_POINTS / cursors.len().max(1));
+        cursors
+            .iter()
+            .map(|(id, c)| {
+                let trail = trails.get(id).map_or_else(Vec::new, |t| {
+                    let recent: Vec<_> = t
+                        .iter()
+                        .filter(|(.., at)| at.elapsed() <= life)
+                        .map(|&(x, y, at)| (x, y, at.elapsed().as_millis() as u32))
+                        .collect();
+                    recent[recent.len().saturating_sub(per_peer)..].to_vec()
+                });
+                PeerState {
+                    id: id.clone(),
+                    color: c.color.clone(),
+                    x: c.x,
+                    y: c.y,
+                    trail,
+                }
+            })
+            .collect()
+    }
+
+    fn summary(&self) -> RoomSummary {
+        let cursors = self.cursors.lock().unwrap();
+        let step = cursors.len().div_ceil(Self::WALL_CURSORS).max(1);
+        RoomSummary {
+            room: self.name.clone(),
+            online: self.hub.online.load(Ordering::Relaxed),
+            cursors: cursors.values().step_by(step).cloned().collect(),
+        }
+    }
+}
+
+/// Live rooms by name, each created by its first connection.
+#[derive(Default)]
+struct Rooms {
+    map: std::sync::Mutex<HashMap<String, Room>>,
+}
+
+impl Rooms {
+    const CAPACITY: usize = 1_024;
+
+    /// Join `name`, creating it with `defaults` overridden by `params`. On
+    /// an existing room `params` only count from a moderator, and then
+    /// apply to whoever joins next.
+    fn join(
+        &self,
+        name: &str,
+        defaults: RoomSettings,
+        params: &HashMap<String, String>,
+        moderator: bool,
+    ) -> Room {
+        let mut map = self.map.lock().unwrap();
+        let room = map.entry(name.to_string()).or_insert_with(|| {
+            let settings = defaults.with(params);
+            Room {
+                name: name.to_string(),
+                hub: Hub::new(Self::CAPACITY),
+                settings,
+                cursors: Arc::default(),
+                trails: Arc::default(),
+                last_left: Arc::new(std::sync::Mutex::new(Instant::now())),
+                moves: Arc::default(),
+                flushing: Arc::default(),
+                tick_rate: Arc::new(AtomicU32::new(settings.tick_rate)),
+                frozen: Arc::default(),
+            }
+        });
+        if moderator {
+            room.settings = room.settings.with(params);
+            room.tick_rate
+                .store(room.settings.tick_rate, Ordering::Relaxed);
+        }
+        room.clone()
+    }
+
+    /// Drop rooms nobody has been in for `grace`, returning their names.
+    /// Connections hold a clone of their room, so one only the map holds
+    /// is empty; checking under the map lock means it can't race `join`.
+    fn reap(&self, grace: Duration) -> Vec<String> {
+        let mut reaped = Vec::new();
+        self.map.lock().unwrap().retain(|name, room| {
+            let unused = Arc::strong_count(&room.last_left) == 1;
+            let keep = !unused || room.last_left.lock().unwrap().elapsed() < grace;
+            if !keep {
+                reaped.push(name.clone());
+            }
+            keep
+        });
+        reaped
+    }
+}
+
+/// How often empty rooms are looked for.
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Latest an injected line may be scheduled, after the first one.
+const INJECT_MAX_SPAN: Duration = Duration::from_secs(600);
+
+/// How often every room is sent a `Tick`, so clients can tell a quiet
+/// server from a stalled connection.
+const TICK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The room asked for, if it's a usable name.
+fn room_name(params: &HashMap<String, String>) -> Option<&str> {
+    let name = params.get("room").map_or(DEFAULT_ROOM, String::as_str);
+    let valid = (1..=32).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    valid.then_some(name)
+}
+
+/// Times a connection may fall behind its broadcasts before it counts as slow.
+const SLOW_LAGS: u64 = 3;
+
+/// Server-wide counters, served at `/metrics`.
+#[derive(Default)]
+struct Metrics {
+    /// times a connection fell behind a broadcast channel
+    lagged: AtomicU64,
+    /// broadcasts skipped because of it
+    skipped: AtomicU64,
+    /// live connections that have lagged `SLOW_LAGS` times or more
+    slow_clients: AtomicUsize,
+    /// moves in from clients, and out to rooms; the gap is what coalescing saved
+    moves_received: AtomicU64,
+    moves_relayed: AtomicU64,
+    /// connections dropped because a write to them failed
+    send_failures: AtomicU64,
+    /// times a flooding connection was paused, and how many are right now
+    throttles: AtomicU64,
+    throttled: AtomicUsize,
+    /// moves skipped for being older than `Config::stale_after` by the time they'd go out
+    stale: AtomicU64,
+}
+
+/// Lifts one connection has been sent. A lift goes on `hi`, so it can pass
+/// moves of the same pointer still queued on `lo`; those would bring its
+/// cursor back for good, so they're skipped.
+#[derive(Default)]
+struct Lifts(HashMap<String, Instant>);
+
+impl Lifts {
+    /// How long a lift is remembered: longer than any move sits queued.
+    const KEPT: Duration = Duration::from_secs(60);
+
+    /// Whether `item` should still go out, noting it if it's a lift.
+    fn admits(&mut self, item: &Item) -> bool {
+        let Some(key) = &item.cursor else {
+            return true;
+        };
+        if !item.lossy {
+            self.0.retain(|_, at| at.elapsed() < Self::KEPT);
+            self.0.insert(key.clone(), item.at);
+            return true;
+        }
+        match self.0.get(key) {
+            Some(&lifted) if item.at <= lifted => false,
+            // touched down again since
+            Some(_) => {
+                self.0.remove(key);
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+/// One connection's share of the lag counters. It stays counted as slow
+/// until it closes, however that happens.
+struct Lags {
+    metrics: Arc<Metrics>,
+    id: String,
+    count: u64,
+}
+
+impl Lags {
+    /// Record falling `skipped` broadcasts behind, with `queued` still waiting.
+    fn lagged(&mut self, skipped: u64, queued: usize) {
+        self.metrics.lagged.fetch_add(1, Ordering::Relaxed);
+        self.metrics.skipped.fetch_add(skipped, Ordering::Relaxed);
+        self.count += 1;
+        if self.count == SLOW_LAGS {
+            self.metrics.slow_clients.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.count >= SLOW_LAGS {
+            tracing::warn!(id = %self.id, lags = self.count, skipped, queued, "slow client");
+        } else {
+            tracing::debug!(id = %self.id, skipped, queued, "client lagged");
+        }
+    }
+}
+
+impl Drop for Lags {
+    fn drop(&mut self) {
+        if self.count >= SLOW_LAGS {
+            self.metrics.slow_clients.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Why a write to a client failed, in a word for the logs.
+fn send_failure(err: &axum::Error) -> &'static str {
+    use std::error::Error;
+    use tungstenite::Error as WsError;
+    match err.source().and_then(|e| e.downcast_ref::<WsError>()) {
+        Some(WsError::ConnectionClosed | WsError::AlreadyClosed) => "closed",
+        Some(WsError::Io(e)) => match e.kind() {
+            std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted => "reset",
+            _ => "io",
+        },
+        Some(WsError::Protocol(_)) => "protocol",
+        Some(WsError::Capacity(_) | WsError::WriteBufferFull(_)) => "capacity",
+        _ => webtransport_failure(err),
+    }
+}
+
+/// `send_failure`, for a WebTransport session.
+fn webtransport_failure(err: &axum::Error) -> &'static str {
+    use std::error::Error;
+    use wtransport::error::{SendDatagramError, StreamWriteError};
+    let err = err.source();
+    if let Some(err) = err.and_then(|e| e.downcast_ref::<StreamWriteError>()) {
+        return match err {
+            StreamWriteError::Closed | StreamWriteError::Stopped(_) => "closed",
+            StreamWriteError::NotConnected => "reset",
+            StreamWriteError::QuicProto => "protocol",
+        };
+    }
+    match err.and_then(|e| e.downcast_ref::<SendDatagramError>()) {
+        Some(SendDatagramError::NotConnected) => "reset",
+        _ => "other",
+    }
+}
+
+/// Log and count the write that ended a connection.
+fn send_failed(metrics: &Metrics, id: &str, err: axum::Error) {
+    metrics.send_failures.fetch_add(1, Ordering::Relaxed);
+    match send_failure(&err) {
+        // they went first; nothing to diagnose
+        "closed" => tracing::debug!(%id, "send to closed connection"),
+        reason => tracing::info!(%id, reason, error = %err, "send failed, dropping connection"),
+    }
+}
+
+/// Everything a connection shares with the rest of the server.
+#[derive(Clone)]
+struct App {
+    rooms: Arc<Rooms>,
+    config: Arc<Config>,
+    bans: Arc<Bans>,
+    ids: Arc<Ids>,
+    resumes: Arc<Resumes>,
+    per_ip: Arc<PerIp>,
+    store: Store,
+    metrics: Arc<Metrics>,
+}
+
+async fn ws(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+    Extension(app): Extension<App>,
+) -> axum::response::Response {
+    let ip = client_ip(&headers, addr, app.config.trust_proxy);
+    match admit(&app, ip, &params) {
+        Ok(admitted) => ws.on_upgrade(move |socket| {
+            let (sender, receiver) = socket.split();
+            // nothing but the websocket to send on, lossy or not
+            let sender = sender.with(|out: Outgoing| std::future::ready(Ok(out.msg)));
+            serve(app, admitted, sender, receiver)
+        }),
+        Err(Refusal::Status(status)) => status.into_response(),
+        Err(Refusal::Close(reason)) => ws.on_upgrade(move |mut socket| async move {
+            let _ = socket.send(close(reason)).await;
+        }),
+    }
+}
+
+/// Why a connection was turned away.
+enum Refusal {
+    /// before upgrading, with this status
+    Status(StatusCode),
+    /// once upgraded, with a close the client can tell its user about
+    Close(CloseReason),
+}
+
+/// A connection let in by `admit`, holding its places until it's served.
+struct Admitted {
+    slot: IpSlot,
+    room: Room,
+    seat: Seat,
+    ip: IpAddr,
+    moderator: bool,
+    /// asked for on connect, pinned from the start if it's a color at all
+    color: Option<String>,
+    /// the token of a dropped session to pick up again
+    resume: Option<String>,
+}
+
+/// What a connection from `ip` asking for `params` has to get past, over
+/// either transport.
+fn admit(app: &App, ip: IpAddr, params: &HashMap<String, String>) -> Result<Admitted, Refusal> {
+    let config = &app.config;
+    if app.bans.contains(&ip.to_string()) {
+        return Err(Refusal::Status(StatusCode::FORBIDDEN));
+    }
+
+    // missing = from before versioning; garbage = certainly not something we speak
+    let version = params.get("v").map_or(Some(0), |v| v.parse().ok());
+    if !version.is_some_and(|v| VERSIONS.contains(&v)) {
+        return Err(Refusal::Close(CloseReason::Outdated));
+    }
+
+    let Some(slot) = app.per_ip.acquire(ip, config.max_per_ip) else {
+        return Err(Refusal::Status(StatusCode::TOO_MANY_REQUESTS));
+    };
+
+    let Some(name) = room_name(params) else {
+        return Err(Refusal::Status(StatusCode::BAD_REQUEST));
+    };
+    let moderator =
+        config.admin_token.is_some() && params.get("token") == config.admin_token.as_ref();
+    let room = app.rooms.join(name, config.room, params, moderator);
+    let Some(seat) = room.hub.seat(room.settings.max_peers) else {
+        return Err(Refusal::Close(CloseReason::RoomFull));
+    };
+
+    Ok(Admitted {
+        slot,
+        room,
+        seat,
+        ip,
+        moderator,
+        color: params.get("color").filter(|c| is_hex_color(c)).cloned(),
+        resume: params.get("resume").cloned(),
+    })
+}
+
+/// Serve an admitted connection until it ends, as the session it resumes
+/// if it has a token for one.
+async fn serve<Si, St>(app: App, mut admitted: Admitted, sender: Si, receiver: St)
+where
+    Si: Sink<Outgoing, Error = axum::Error> + Send + Unpin + 'static,
+    St: Stream<Item = Result<Message, axum::Error>> + Send + Unpin + 'static,
+{
+    // a parked session's id is still reserved, so it's safe to hand back
+    let resumed = admitted
+        .resume
+        .take()
+        .and_then(|token| app.resumes.take(&token, &admitted.room.name))
+        .filter(|p| !app.bans.contains(&p.id));
+    let id = match resumed {
+        Some(p) => {
+            tracing::debug!(id = %p.id, "resumed");
+            admitted.color = p.color.or(admitted.color);
+            p.id
+        }
+        None => app.ids.reserve(new_id),
+    };
+    let parked = client(sender, receiver, id.clone(), app.clone(), admitted).await;
+    if !parked {
+        app.ids.release(&id);
+    }
+}
+
+/// What `client` writes. `lossy` marks a move the next one supersedes,
+/// which a transport that has one may send unreliably.
+struct Outgoing {
+    msg: Message,
+    lossy: bool,
+}
+
+impl From<Message> for Outgoing {
+    fn from(msg: Message) -> Self {
+        Self { msg, lossy: false }
+    }
+}
+
+/// Client tunables. The page is served from another origin, hence the CORS header.
+async fn tunables(Extension(app): Extension<App>) -> impl IntoResponse {
+    (
+        [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(app.config.tunables.clone()),
+    )
+}
+
+/// A room's broadcasts as server-sent events (`/events?room=..`), for
+/// dashboards that only watch. Nothing is counted as a connection, and
+/// dropping the response drops the subscription.
+async fn events(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(app): Extension<App>,
+) -> axum::response::Response {
+    let Some(name) = room_name(&params) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    // held by the stream, so the room isn't reclaimed while it's watched
+    let room = app
+        .rooms
+        .join(name, app.config.room, &HashMap::new(), false);
+    let subscriptions = (room.hub.hi.subscribe(), room.hub.lo.subscribe(), room);
+    let stream = futures_util::stream::unfold(subscriptions, |(mut hi, mut lo, room)| async move {
+        loop {
+            let item = tokio::select! {
+                biased;
+                item = hi.recv() => item,
+                item = lo.recv() => item,
+            };
+            match item {
+                Ok(item) => {
+                    let event = Event::default().data(item.txt);
+                    return Some((Ok::<_, std::convert::Infallible>(event), (hi, lo, room)));
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    (
+        [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
+}
+
+/// Every room at a glance, for a big screen showing them side by side.
+/// Moderators only: `/wall?token=<ADMIN_TOKEN>`.
+async fn wall(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(app): Extension<App>,
+) -> axum::response::Response {
+    let token = app.config.admin_token.as_ref();
+    if token.is_none() || params.get("token") != token {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let mut rooms: Vec<RoomSummary> = {
+        let map = app.rooms.map.lock().unwrap();
+        map.values().map(Room::summary).collect()
+    };
+    rooms.sort_by(|a, b| a.room.cmp(&b.room));
+    Json(rooms).into_response()
+}
+
+/// Everything the relay is holding, for digging into live problems: rooms
+/// with their settings and last known cursors, open connections, and the
+/// counters. Moderators only (`/debug/state?token=<ADMIN_TOKEN>`), and not
+/// routed at all without a token.
+async fn debug_state(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(app): Extension<App>,
+) -> axum::response::Response {
+    if params.get("token") != app.config.admin_token.as_ref() {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let mut rooms: Vec<_> = {
+        let map = app.rooms.map.lock().unwrap();
+        map.values()
+            .map(|r| {
+                let online = r.hub.online.load(Ordering::Relaxed);
+                serde_json::json!({
+                    "room": r.name,
+                    "online": online,
+                    "max_peers": r.settings.max_peers,
+                    "tick_rate": r.settings.tick_rate,
+                    "persist": r.settings.persist,
+                    "empty_for_secs": (online == 0)
+                        .then(|| r.last_left.lock().unwrap().elapsed().as_secs()),
+                    "cursors": *r.cursors.lock().unwrap(),
+                    "pending_moves": r.moves.lock().unwrap().len(),
+                    "flushing": r.flushing.load(Ordering::Relaxed),
+                })
+            })
+            .collect()
+    };
+    rooms.sort_by(|a, b| a["room"].as_str().cmp(&b["room"].as_str()));
+    let m = &app.metrics;
+    Json(serde_json::json!({
+        "rooms": rooms,
+        "sessions": app.ids.0.lock().unwrap().len(),
+        "addresses": app.per_ip.0.lock().unwrap().len(),
+        "bans": app.bans.set.lock().unwrap().len(),
+        "counters": {
+            "lagged": m.lagged.load(Ordering::Relaxed),
+            "skipped": m.skipped.load(Ordering::Relaxed),
+            "slow_clients": m.slow_clients.load(Ordering::Relaxed),
+            "moves_received": m.moves_received.load(Ordering::Relaxed),
+            "moves_relayed": m.moves_relayed.load(Ordering::Relaxed),
+            "send_failures": m.send_failures.load(Ordering::Relaxed),
+            "throttles": m.throttles.load(Ordering::Relaxed),
+            "throttled": m.throttled.load(Ordering::Relaxed),
+            "stale": m.stale.load(Ordering::Relaxed),
+        },
+    }))
+    .into_response()
+}
+
+/// One line of an `/inject` body: a relayed message, and optionally when to
+/// send it in ms. Recordings the client replays have the same shape.
+#[derive(serde::Deserialize)]
+struct Injected {
+    #[serde(default)]
+    t: Option<f64>,
+    #[serde(flatten)]
+    msg: Msg,
+}
+
+/// Validate an `/inject` body. Returns what to send, as offsets from the
+/// earliest `t` (untimed lines follow the line before), and the 1-based
+/// numbers of lines that were refused. Blank lines are neither.
+fn parse_injected(body: &str, extra: &ExtraPolicy) -> (Vec<(Duration, Msg)>, Vec<usize>) {
+    let mut rejected = Vec::new();
+    let parsed: Vec<_> = body
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str::<Injected>(line) {
+            Ok(line) if line.msg.kind().is_some() && line.msg.color().is_none_or(is_hex_color) => {
+                Some((i + 1, line))
+            }
+            _ => {
+                rejected.push(i + 1);
+                None
+            }
+        })
+        .collect();
+    let t0 = parsed
+        .iter()
+        .filter_map(|(_, line)| line.t)
+        .reduce(f64::min)
+        .unwrap_or(0.0);
+
+    let mut accepted = Vec::new();
+    let mut at = Duration::ZERO;
+    for (n, Injected { t, mut msg }) in parsed {
+        if let Some(t) = t {
+            match Duration::try_from_secs_f64((t - t0) / 1_000.0) {
+                Ok(offset) if offset <= INJECT_MAX_SPAN => at = offset,
+                _ => {
+                    rejected.push(n);
+                    continue;
+                }
+            }
+        }
+        if let Msg::Move(pkt) = &mut msg {
+            extra.apply(&mut pkt.extra);
+        }
+        accepted.push((at, msg));
+    }
+    rejected.sort_unstable();
+    accepted.sort_by_key(|(at, _)| *at);
+    (accepted, rejected)
+}
+
+/// Feed a JSON-Lines body of relayed messages into a room as if virtual
+/// clients sent them, for seeding demos and replaying bug reports.
+/// Moderators only (`POST /inject?token=<ADMIN_TOKEN>&room=..`), and not
+/// routed without a token. Lines with a `t` (ms) are played back at those
+/// intervals in the background; the response says what was accepted.
+async fn inject(
+    Query(params): Query<HashMap<String, String>>,
+    Extension(app): Extension<App>,
+    body: String,
+) -> axum::response::Response {
+    if params.get("token") != app.config.admin_token.as_ref() {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(name) = room_name(&params) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let (lines, rejected) = parse_injected(&body, &app.config.extra);
+    let accepted = lines.len();
+    let span = lines.last().map_or(Duration::ZERO, |(at, _)| *at);
+    // held until the last line is out, so the room isn't reclaimed meanwhile
+    let room = app
+        .rooms
+        .join(name, app.config.room, &HashMap::new(), false);
+    tracing::info!(
+        room = name,
+        accepted,
+        rejected = rejected.len(),
+        ?span,
+        "injecting"
+    );
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now();
+        for (at, msg) in lines {
+            tokio::time::sleep_until(start + at).await;
+            if matches!(msg, Msg::Move(_)) && room.frozen.load(Ordering::Relaxed) {
+                continue;
+            }
+            room.hub.send(msg);
+        }
+    });
+    Json(serde_json::json!({
+        "accepted": accepted,
+        "rejected": rejected,
+        "span_ms": span.as_millis() as u64,
+    }))
+    .into_response()
+}
+
+/// Counters in Prometheus' text format.
+async fn metrics(Extension(app): Extension<App>) -> String {
+    let (rooms, online) = {
+        let map = app.rooms.map.lock().unwrap();
+        let online: usize = map
+            .values()
+            .map(|r| r.hub.online.load(Ordering::Relaxed))
+            .sum();
+        (map.len(), online)
+    };
+    let m = &app.metrics;
+    let series = [
+        (
+            "connections",
+            "gauge",
+            "Live websocket connections.",
+            online as u64,
+        ),
+        ("rooms", "gauge", "Open rooms.", rooms as u64),
+        (
+            "lagged_total",
+            "counter",
+            "Times a connection fell behind its broadcasts.",
+            m.lagged.load(Ordering::Relaxed),
+        ),
+        (
+            "skipped_total",
+            "counter",
+            "Broadcasts skipped by lagging connections.",
+            m.skipped.load(Ordering::Relaxed),
+        ),
+        (
+            "slow_clients",
+            "gauge",
+            "Live connections that keep lagging.",
+            m.slow_clients.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "moves_received_total",
+            "counter",
+            "Moves received from clients.",
+            m.moves_received.load(Ordering::Relaxed),
+        ),
+        (
+            "moves_relayed_total",
+            "counter",
+            "Moves broadcast to rooms, after coalescing.",
+            m.moves_relayed.load(Ordering::Relaxed),
+        ),
+        (
+            "send_failures_total",
+            "counter",
+            "Connections dropped after a failed write.",
+            m.send_failures.load(Ordering::Relaxed),
+        ),
+        (
+            "throttles_total",
+            "counter",
+            "Times a connection that kept hitting rate limits was paused.",
+            m.throttles.load(Ordering::Relaxed),
+        ),
+        (
+            "throttled_clients",
+            "gauge",
+            "Connections paused for flooding right now.",
+            m.throttled.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "stale_moves_total",
+            "counter",
+            "Moves skipped because a connection only got to them after STALE_AFTER.",
+            m.stale.load(Ordering::Relaxed),
+        ),
+    ];
+    series
+        .iter()
+        .map(|(name, kind, help, value)| {
+            format!("# HELP femtanyl_{name} {help}\n# TYPE femtanyl_{name} {kind}\nfemtanyl_{name} {value}\n")
+        })
+        .collect()
+}
+
+/// The caller's address. On Fly.io every connection comes from the edge proxy,
+/// so the real client is in `Fly-Client-IP` (or `X-Forwarded-For`). Those
+/// headers are client-controlled when not behind the proxy, so run with
+/// `TRUST_PROXY=false` anywhere the server is reachable directly.
+fn client_ip(headers: &HeaderMap, addr: SocketAddr, trust_proxy: bool) -> IpAddr {
+    let forwarded = || {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        header("fly-client-ip")
+            .or_else(|| header("x-forwarded-for").and_then(|v| v.split(',').next()))
+            .and_then(|ip| ip.trim().parse().ok())
+    };
+    trust_proxy.then(forwarded).flatten().unwrap_or(addr.ip())
+}
+
+/// Serve one connection as `id` until it ends, whichever transport `sender`
+/// and `receiver` are. Its color, if it asked for one on connect, is pinned
+/// from the start rather than taken from its first message. Returns whether
+/// its session was parked for a resume, in which case the id stays reserved
+/// until `RESUME_GRACE` has passed.
+async fn client<Si, St>(
+    mut sender: Si,
+    mut receiver: St,
+    id: String,
+    app: App,
+    admitted: Admitted,
+) -> bool
+where
+    Si: Sink<Outgoing, Error = axum::Error> + Send + Unpin + 'static,
+    St: Stream<Item = Result<Message, axum::Error>> + Send + Unpin + 'static,
+{
+    let Admitted {
+        slot: _slot,
+        room,
+        seat,
+        ip,
+        moderator,
+        color,
+        ..
+    } = admitted;
+    let trail_life = Duration::from_secs_f64(app.config.tunables.trail_life.max(0.0) / 1_000.0);
+    let snapshot = room.snapshot(app.config.snapshot_trail, trail_life);
+    let App {
+        config,
+        bans,
+        ids,
+        resumes,
+        store,
+        metrics,
+        ..
+    } = app;
+    let Room {
+        name: room,
+        hub,
+        settings,
+        cursors,
+        trails,
+        last_left,
+        moves,
+        flushing,
+        tick_rate,
+        frozen,
+    } = room;
+    if config.coalesce && !flushing.swap(true, Ordering::Relaxed) {
+        let (hub, moves, metrics) = (hub.clone(), moves.clone(), metrics.clone());
+        tokio::spawn(flush_moves(hub, moves, tick_rate, metrics));
+    }
+    let mut hi_rx = hub.hi.subscribe();
+    let mut lo_rx = hub.lo.subscribe();
+    hub.presence();
+
+    // presented on reconnect to pick this session up again, see `Resumes`
+    let resume = Uuid::new_v4().simple().to_string();
+    let first_color = color.clone();
+
+    // kinds this client wants forwarded (everything until told otherwise)
+    let (sub_tx, sub_rx) = watch::channel(HashSet::from(Kind::ALL));
+
+    // messages for this client only (close frames, ...)
+    let (direct_tx, mut direct_rx) = mpsc::unbounded_channel::<Message>();
+
+    let welcome = Msg::Welcome(Welcome {
+        id: Some(id.clone()),
+        color: color.clone(),
+        online: Some(hub.online.load(Ordering::Relaxed)),
+        room: Some(room.clone()),
+        max_peers: Some(settings.max_peers),
+        persist: Some(settings.persist),
+        version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        tick_rate: Some(settings.tick_rate),
+        frozen: Some(frozen.load(Ordering::Relaxed)),
+        resume: Some(resume.clone()),
+    });
+    if let Some(txt) = encode(&welcome) {
+        let _ = direct_tx.send(Message::Text(txt));
+    }
+    // even when empty: clients take it as the whole board, and drop whoever isn't on it
+    if let Some(txt) = encode(&Msg::Snapshot { peers: snapshot }) {
+        let _ = direct_tx.send(Message::Text(txt));
+    }
+
+    // catch up on the board before anything live (direct messages go first)
+    let history = match settings.persist {
+        true => store.history(&room).await,
+        false => Vec::new(),
+    };
+    for stroke in history {
+        let msg = Stamped {
+            seq: None,
+            lossy: false,
+            supersedes: None,
+            msg: Msg::Stroke(stroke),
+        };
+        if let Some(txt) = encode(&msg) {
+            let _ = direct_tx.send(Message::Text(txt));
+        }
+    }
+
+    // inactivity timer
+    let last_seen = Arc::new(Mutex::new(Instant::now()));
+    let idle_timeout = config.idle_timeout;
+
+    // fan-out: broadcast -> this client, draining important events first
+    let mut lags = Lags {
+        metrics: metrics.clone(),
+        id: id.clone(),
+        count: 0,
+    };
+    let stale_after = config.stale_after;
+    let mut lifts = Lifts::default();
+    let mut send_task = tokio::spawn(async move {
+        loop {
+            let item = tokio::select! {
+                biased;
+                msg = direct_rx.recv() => {
+                    let Some(msg) = msg else { break };
+                    let closing = matches!(msg, Message::Close(_));
+                    if let Err(err) = sender.send(msg.into()).await {
+                        send_failed(&lags.metrics, &lags.id, err);
+                        break;
+                    }
+                    if closing {
+                        break;
+                    }
+                    continue;
+                }
+                item = hi_rx.recv() => item,
+                item = lo_rx.recv() => item,
+            };
+            let item = match item {
+                Ok(item) => item,
+                // fell behind: skip what was dropped and carry on with fresher data
+                Err(RecvError::Lagged(skipped)) => {
+                    lags.lagged(skipped, hi_rx.len() + lo_rx.len());
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            if item.kind.is_some_and(|k| !sub_rx.borrow().contains(&k)) {
+                continue;
+            }
+            // backed up: skip to positions that are still current
+            if item.stale(stale_after) {
+                lags.metrics.stale.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if !lifts.admits(&item) {
+                continue;
+            }
+            let out = Outgoing {
+                msg: Message::Text(item.txt),
+                lossy: item.lossy,
+            };
+            if let Err(err) = sender.send(out).await {
+                send_failed(&lags.metrics, &lags.id, err);
+                break;
+            }
+        }
+    });
+
+    let kicked = ids.kicked(&id);
+
+    // moderator bans naming this session or address end it
+    let mut banned_rx = bans.notify.subscribe();
+    let banned = {
+        let (id, ip) = (id.clone(), ip.to_string());
+        async move {
+            loop {
+                match banned_rx.recv().await {
+                    Ok(target) if target == id || target == ip => break,
+                    Err(RecvError::Closed) => std::future::pending::<()>().await,
+                    _ => {}
+                }
+            }
+        }
+    };
+
+    // fan-in: this client -> broadcast
+    let hub_tx = hub.clone();
+    let moves_rx = moves.clone();
+    let counters = metrics;
+    let sender_id = id.clone();
+    let reply_tx = direct_tx.clone();
+    let last_seen_rx = last_seen.clone();
+    let cursors_rx = cursors.clone();
+    let trails_rx = trails.clone();
+    // still wanted once the connection is done, to park it
+    let (parked_room, session_ids) = (room.clone(), ids.clone());
+    let resumes_rx = resumes.clone();
+    let mut recv_task = tokio::spawn(async move {
+        // requested on connect, else the first seen, or the last accepted change;
+        // clients can't spoof per packet
+        let mut color = color;
+        let mut recolored: Option<Instant> = None;
+        let mut marked: Option<Instant> = None;
+        let mut chat_limit = ChatLimit::default();
+        // where each of its cursors last was, for the speed limit
+        let mut last_moves: HashMap<Option<u8>, ((f32, f32), Instant)> = HashMap::new();
+        // logged loudly once, then quietly, since it'll likely be every move
+        let mut stripped = false;
+        let mut flood = Flood::default();
+        let mut move_limit = MoveLimit::new(settings.tick_rate);
+        let mut pause: Option<Duration> = None;
+
+        loop {
+            // a flooding client is left unread a while, so it backs up on its own side
+            if let Some(pause) = pause.take() {
+                tracing::info!(id = %sender_id, level = flood.level, ?pause, "throttling flooding client");
+                let text = format!("slow down: ignoring you for {}s", pause.as_secs().max(1));
+                if let Some(notice) = encode(&Msg::Notice { text }) {
+                    let _ = reply_tx.send(Message::Text(notice));
+                }
+                let _held = Throttled::hold(&counters);
+                tokio::time::sleep(pause).await;
+            }
+            let txt = match receiver.next().await {
+                Some(Ok(Message::Text(txt))) => txt,
+                // a close frame is the client leaving, anything else the link dropping
+                Some(Ok(Message::Close(_))) => break true,
+                _ => break false,
+            };
+            let msg = Msg::decode(&txt);
+
+            // answered directly, and not counted as activity
+            if let Some(Msg::Ping { t }) = msg {
+                if let Some(pong) = encode(&Msg::Pong { t }) {
+                    let _ = reply_tx.send(Message::Text(pong));
+                }
+                continue;
+            }
+            *last_seen_rx.lock().await = Instant::now();
+
+            let mut msg = match msg {
+                Some(Msg::Subscribe { kinds }) => {
+                    let _ = sub_tx.send(kinds.into_iter().collect());
+                    continue;
+                }
+                // only the server ends sessions
+                Some(Msg::Close { .. }) => continue,
+                Some(Msg::Ban { target }) => {
+                    if moderator {
+                        resumes_rx.revoke(&target);
+                        bans.add(target);
+                    }
+                    continue;
+                }
+                Some(Msg::Kick { id: target }) => {
+                    // `|`, not `||`: a parked session can't come back either
+                    if moderator && (resumes_rx.revoke(&target) | ids.kick(&target)) {
+                        tracing::info!(id = %target, by = %sender_id, "kicked");
+                    }
+                    continue;
+                }
+                Some(Msg::Freeze { frozen: on }) => {
+                    if moderator && frozen.swap(on, Ordering::Relaxed) != on {
+                        tracing::info!(%room, by = %sender_id, frozen = on, "freeze");
+                        // drop what's waiting for the next tick, or it'd slip out after
+                        moves_rx.lock().unwrap().clear();
+                        hub_tx.announce(Msg::Freeze { frozen: on });
+                    }
+                    continue;
+                }
+                Some(Msg::SetColor { color: c }) => {
+                    if !is_hex_color(&c) {
+                        continue;
+                    }
+                    if recolored.is_none_or(|t| t.elapsed() >= RECOLOR_INTERVAL) {
+                        color = Some(c);
+                        recolored = Some(Instant::now());
+                    } else {
+                        pause = flood.violated(Instant::now());
+                    }
+                    continue;
+                }
+                // a quick second click is just dropped, not held against anyone
+                Some(Msg::Move(pkt)) if !move_limit.allows(pkt.pointer, Instant::now()) => {
+                    pause = flood.violated(Instant::now());
+                    continue;
+                }
+                Some(msg @ Msg::Mark(_)) => {
+                    if marked.is_some_and(|t| t.elapsed() < MARK_INTERVAL) {
+                        continue;
+                    }
+                    marked = Some(Instant::now());
+                    msg
+                }
+                Some(Msg::Chat(mut chat)) => {
+                    if let Err(text) = chat_limit.check(Instant::now()) {
+                        if let Some(notice) = encode(&Msg::Notice { text: text.into() }) {
+                            let _ = reply_tx.send(Message::Text(notice));
+                        }
+                        pause = flood.violated(Instant::now());
+                        continue;
+                    }
+                    if let Some((cut, _)) = chat.text.char_indices().nth(CHAT_MAX_LEN) {
+                        chat.text.truncate(cut);
+                    }
+                    Msg::Chat(chat)
+                }
+                Some(msg) => msg,
+                None => continue,
+            };
+
+            // pinned from the first message that has one, if it's a color at all
+            match &color {
+                Some(c) => msg.set_color(c),
+                None if msg.color().is_some_and(|c| !is_hex_color(c)) => continue,
+                None => color = msg.color().map(String::from),
+            }
+            msg.set_id(&sender_id);
+            if let Msg::Move(pkt) = &msg {
+                if pkt
+                    .pointer
+                    .is_some_and(|p| p == 0 || p > Packet::MAX_POINTERS)
+                {
+                    continue;
+                }
+            }
+            if let (Msg::Move(pkt), Some(max)) = (&mut msg, config.max_speed) {
+                let last = last_moves.remove(&pkt.pointer);
+                let last = limit_speed(pkt, last, max, &sender_id);
+                if !pkt.lifted {
+                    last_moves.insert(pkt.pointer, last);
+                }
+            }
+            if let Msg::Move(pkt) = &mut msg {
+                if config.extra.apply(&mut pkt.extra) {
+                    match stripped {
+                        false => {
+                            tracing::info!(id = %sender_id, "stripped extra fields from a move")
+                        }
+                        true => {
+                            tracing::debug!(id = %sender_id, "stripped extra fields from a move")
+                        }
+                    }
+                    stripped = true;
+                }
+            }
+            if let Msg::Move(pkt) = &msg {
+                let (x, y) = pkt.pos();
+                let color = pkt.color.clone();
+                let cursor = Cursor { color, x, y };
+                let key = pkt.cursor_key();
+                if pkt.lifted {
+                    cursors_rx.lock().unwrap().remove(&key);
+                    trails_rx.lock().unwrap().remove(&key);
+                } else {
+                    cursors_rx.lock().unwrap().insert(key.clone(), cursor);
+                }
+                if config.snapshot_trail > 0 && !pkt.lifted {
+                    let mut trails = trails_rx.lock().unwrap();
+                    let trail = trails.entry(key).or_default();
+                    if trail.len() >= config.snapshot_trail {
+                        trail.pop_front();
+                    }
+                    trail.push_back((x, y, Instant::now()));
+                }
+            }
+            if let (Msg::Stroke(stroke), true) = (&msg, settings.persist) {
+                store.save(&room, stroke.clone());
+            }
+            if let Msg::Move(_) = msg {
+                counters.moves_received.fetch_add(1, Ordering::Relaxed);
+            }
+            let msg = match msg {
+                // still tracked above, so `/wall` and unfreezing pick up from here
+                Msg::Move(_) if frozen.load(Ordering::Relaxed) => continue,
+                // the lift, being the last, is what goes out
+                Msg::Move(pkt) if config.coalesce => {
+                    moves_rx.lock().unwrap().insert(pkt.cursor_key(), pkt);
+                    continue;
+                }
+                Msg::Move(pkt) => {
+                    counters.moves_relayed.fetch_add(1, Ordering::Relaxed);
+                    Msg::Move(pkt)
+                }
+                msg => msg,
+            };
+            hub_tx.send(msg);
+        }
+    });
+
+    // disconnect participants after `idle_timeout` of silence
+    let mut watchdog = tokio::spawn({
+        let (last_seen, timeout) = (last_seen.clone(), idle_timeout);
+        async move {
+            loop {
+                tokio::time::sleep(timeout).await;
+                if last_seen.lock().await.elapsed() >= timeout {
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut dropped = false;
+    let reason = tokio::select! {
+      _ = (&mut send_task) => { recv_task.abort(); dropped = true; None },
+      left = (&mut recv_task) => {
+          send_task.abort();
+          dropped = left.is_ok_and(|closed| !closed);
+          None
+      },
+      _ = &mut watchdog => Some(CloseReason::Timeout),
+      _ = banned => Some(CloseReason::Banned),
+      Ok(()) = kicked => Some(CloseReason::Kicked),
+    };
+    watchdog.abort();
+
+    // server-initiated: say why before hanging up
+    if let Some(reason) = reason {
+        recv_task.abort();
+        let _ = direct_tx.send(close(reason));
+        if tokio::time::timeout(CLOSE_GRACE, &mut send_task)
+            .await
+            .is_err()
+        {
+            send_task.abort();
+        }
+    }
+
+    // under the lock `flush_moves` relays with, so none are relayed after the leave;
+    // ones already relayed may still reach a backed-up peer behind it, which
+    // clients ignore
+    moves
+        .lock()
+        .unwrap()
+        .retain(|key, _| cursor_owner(key) != id);
+
+    // lost rather than left: keep the cursor where it was for a while, in
+    // case it reconnects, and only tell peers once that's unlikely
+    if dropped {
+        let color = cursors.lock().unwrap().get(&id).map(|c| c.color.clone());
+        let parked = Parked {
+            id: id.clone(),
+            color: color.or(first_color),
+            room: parked_room,
+            at: Instant::now(),
+            revoked: false,
+        };
+        resumes.park(resume.clone(), parked);
+        drop(seat);
+        hub.presence();
+        *last_left.lock().unwrap() = Instant::now();
+        tokio::spawn(async move {
+            tokio::time::sleep(RESUME_GRACE).await;
+            if resumes.expire(&resume).is_some() {
+                cursors
+                    .lock()
+                    .unwrap()
+                    .retain(|key, _| cursor_owner(key) != id);
+                trails
+                    .lock()
+                    .unwrap()
+                    .retain(|key, _| cursor_owner(key) != id);
+                hub.announce(Msg::Leave { id: id.clone() });
+                session_ids.release(&id);
+            }
+        });
+        return true;
+    }
+
+    cursors
+        .lock()
+        .unwrap()
+        .retain(|key, _| cursor_owner(key) != id);
+    trails
+        .lock()
+        .unwrap()
+        .retain(|key, _| cursor_owner(key) != id);
+    hub.announce(Msg::Leave { id });
+    drop(seat);
+    hub.presence();
+    *last_left.lock().unwrap() = Instant::now();
+    false
+}
+
+/// One connection's recent rate limit hits, for flood control.
+#[derive(Default)]
+struct Flood {
+    hits: VecDeque<Instant>,
+    last: Option<Instant>,
+    /// pauses served since it last behaved for `FLOOD_COOLDOWN`
+    level: u32,
+}
+
+impl Flood {
+    /// Count a limit hit at `now`; returns how long to pause the connection
+    /// once there have been too many.
+    fn violated(&mut self, now: Instant) -> Option<Duration> {
+        if self
+            .last
+            .replace(now)
+            .is_some_and(|t| now.duration_since(t) >= FLOOD_COOLDOWN)
+        {
+            self.level = 0;
+        }
+        while self
+            .hits
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= FLOOD_WINDOW)
+        {
+            self.hits.pop_front();
+        }
+        self.hits.push_back(now);
+        if self.hits.len() < FLOOD_STRIKES {
+            return None;
+        }
+        self.hits.clear();
+        self.level += 1;
+        Some(
+            FLOOD_PAUSE
+                .saturating_mul(1 << (self.level - 1).min(16))
+                .min(FLOOD_MAX_PAUSE),
+        )
+    }
+}
+
+/// One connection's moves in the current second, per pointer, for the
+/// `MOVE_SLACK` allowance.
+struct MoveLimit {
+    per_second: u32,
+    counts: HashMap<Option<u8>, (Instant, u32)>,
+}
+
+impl MoveLimit {
+    fn new(tick_rate: u32) -> Self {
+        Self {
+            per_second: tick_rate.saturating_mul(MOVE_SLACK),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Whether a move of `pointer` at `now` is within the allowance.
+    fn allows(&mut self, pointer: Option<u8>, now: Instant) -> bool {
+        let (since, n) = self.counts.entry(pointer).or_insert((now, 0));
+        if now.duration_since(*since) >= Duration::from_secs(1) {
+            (*since, *n) = (now, 0);
+        }
+        *n += 1;
+        *n <= self.per_second
+    }
+}
+
+/// Counts a connection as throttled while held.
+struct Throttled<'a>(&'a Metrics);
+
+impl<'a> Throttled<'a> {
+    fn hold(metrics: &'a Metrics) -> Self {
+        metrics.throttles.fetch_add(1, Ordering::Relaxed);
+        metrics.throttled.fetch_add(1, Ordering::Relaxed);
+        Self(metrics)
+    }
+}
+
+impl Drop for Throttled<'_> {
+    fn drop(&mut self) {
+        self.0.throttled.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// One connection's recent chat, for the rate limit.
+#[derive(Default)]
+struct ChatLimit {
+    sent: VecDeque<Instant>,
+    strikes: u32,
+    muted_until: Option<Instant>,
+}
+
+impl ChatLimit {
+    /// Whether a message sent `now` may go out; if not, what to tell the sender.
+    fn check(&mut self, now: Instant) -> Result<(), &'static str> {
+        if self.muted_until.is_some_and(|t| now < t) {
+            return Err("you're muted for spamming, try again in a minute");
+        }
+        while self
+            .sent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= CHAT_WINDOW)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.is_empty() {
+            self.strikes = 0;
+        }
+
+        if self.sent.len() < CHAT_BURST {
+            self.sent.push_back(now);
+            return Ok(());
+        }
+        self.strikes += 1;
+        if self.strikes < CHAT_STRIKES {
+            return Err("slow down, chat is rate limited");
+        }
+        tracing::info!("muting a chat spammer");
+        self.muted_until = Some(now + CHAT_MUTE);
+        self.sent.clear();
+        Err("you're muted for spamming, try again in a minute")
+    }
+}
+
+/// Relay each sender's latest move once per tick, at whatever `tick_rate`
+/// the room is at, until the room is gone (nothing but this task holds
+/// `moves`).
+async fn flush_moves(
+    hub: Hub,
+    moves: Arc<std::sync::Mutex<HashMap<String, Packet>>>,
+    tick_rate: Arc<AtomicU32>,
+    metrics: Arc<Metrics>,
+) {
+    let mut rate = tick_rate.load(Ordering::Relaxed);
+    let mut tick = tokio::time::interval(Duration::from_secs(1) / rate);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    while Arc::strong_count(&moves) > 1 {
+        tick.tick().await;
+        // a moderator changed it since the last tick
+        if tick_rate.load(Ordering::Relaxed) != rate {
+            rate = tick_rate.load(Ordering::Relaxed);
+            let period = Duration::from_secs(1) / rate;
+            tick = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        }
+        let mut pending = moves.lock().unwrap();
+        let n = pending.len() as u64;
+        for (_, pkt) in pending.drain() {
+            hub.send(Msg::Move(pkt));
+        }
+        metrics.moves_relayed.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+/// Pull a move that implies teleporting back to the fastest allowed travel
+/// from the `last` one of the same cursor, so peers see a fast cursor
+/// rather than a jump. Returns where it ended up, for the next.
+fn limit_speed(
+    pkt: &mut Packet,
+    last: Option<((f32, f32), Instant)>,
+    max: f32,
+    id: &str,
+) -> ((f32, f32), Instant) {
+    let now = Instant::now();
+    let (x, y) = pkt.pos();
+    if let Some(((lx, ly), at)) = last {
+        let (dx, dy) = (x - lx, y - ly);
+        let dist = (dx * dx + dy * dy).sqrt();
+        let allowed = max * now.duration_since(at).as_secs_f32();
+        if dist > allowed {
+            let k = allowed / dist;
+            pkt.set_pos(lx + dx * k, ly + dy * k);
+            tracing::info!(id, dist, allowed, "clamped move over MAX_SPEED");
+        }
+    }
+    (pkt.pos(), now)
+}
+
+/// Serialize for the wire. Relayed messages carry client-supplied `extra`
+/// fields, so a failure is logged and the message dropped, never a panic.
+fn encode<T: serde::Serialize>(msg: &T) -> Option<String> {
+    serde_json::to_string(msg)
+        .map_err(|e| tracing::warn!(%e, "dropping unserializable message"))
+        .ok()
+}
+
+fn close(reason: CloseReason) -> Message {
+    Message::Close(Some(CloseFrame {
+        code: reason.code(),
+        reason: reason.as_str().into(),
+    }))
+}
+
+/// Lets code outside the relay talk to whoever is connected.
+#[derive(Clone)]
+pub struct Handle {
+    rooms: Arc<Rooms>,
+}
+
+impl Handle {
+    /// Send `msg` to everyone in `room` as if the server said it. Relayed
+    /// kinds respect subscriptions, control messages reach everyone. Rooms
+    /// nobody has joined yet are skipped.
+    pub fn send(&self, room: &str, msg: Msg) {
+        let Some(room) = self.rooms.map.lock().unwrap().get(room).cloned() else {
+            return;
+        };
+        match msg.kind() {
+            Some(_) => room.hub.send(msg),
+            None => room.hub.announce(msg),
+        }
+    }
+}
+
+/// The relay's routes, ready to serve or to nest in a larger app: `/ws`,
+/// `/config`, `/metrics`, `/wall` and `/events`, plus `/debug/state` and
+/// `/inject` when `admin_token` is set. Serve with
+/// `into_make_service_with_connect_info::<SocketAddr>()`, `/ws` needs the
+/// peer address.
+///
+/// Starts the background tasks (room reaping, ticks, the store's writer)
+/// with `tokio::spawn`, so it must be called inside a Tokio runtime, or it panics.
+pub fn router(config: Config) -> rusqlite::Result<Router> {
+    Ok(app(config)?.0)
+}
+
+/// [`router`], plus a [`Handle`] for pushing messages into its rooms. Like
+/// it, needs to be called inside a Tokio runtime.
+pub fn app(config: Config) -> rusqlite::Result<(Router, Handle)> {
+    let store = Store::open(config.db_path.as_deref())?;
+    let rooms = Arc::new(Rooms::default());
+    tokio::spawn({
+        let (rooms, grace) = (rooms.clone(), config.room_grace);
+        async move {
+            loop {
+                tokio::time::sleep(REAP_INTERVAL).await;
+                for name in rooms.reap(grace) {
+                    tracing::info!(room = %name, "reclaimed empty room");
+                }
+            }
+        }
+    });
+    let debug = config.admin_token.is_some();
+    tokio::spawn({
+        let rooms = rooms.clone();
+        async move {
+            let mut tick = tokio::time::interval(TICK_INTERVAL);
+            loop {
+                tick.tick().await;
+                let map = rooms.map.lock().unwrap();
+                for room in map.values() {
+                    room.hub.announce(Msg::Tick);
+                }
+            }
+        }
+    });
+    let app = App {
+        rooms: rooms.clone(),
+        config: Arc::new(config),
+        bans: Arc::new(Bans::from_env()),
+        ids: Arc::new(Ids::default()),
+        resumes: Arc::new(Resumes::default()),
+        per_ip: Arc::new(PerIp::default()),
+        store,
+        metrics: Arc::new(Metrics::default()),
+    };
+    tokio::spawn({
+        let app = app.clone();
+        async move {
+            if let Err(e) = webtransport::listen(app).await {
+                tracing::error!(error = %e, "no webtransport endpoint");
+            }
+        }
+    });
+    let mut router = Router::new()
+        .route("/ws", get(ws))
+        .route("/config", get(tunables))
+        .route("/metrics", get(metrics))
+        .route("/wall", get(wall))
+        .route("/events", get(events));
+    if debug {
+        router = router
+            .route("/debug/state", get(debug_state))
+            .route("/inject", post(inject));
+    }
+    let router = router.layer(Extension(app));
+    Ok((router, Handle { rooms }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::Lines;
+
+    /// Room limits the tests run with, unless they're what's under test.
+    fn settings() -> RoomSettings {
+        RoomSettings {
+            persist: false,
+            ..RoomSettings::default()
+        }
+    }
+
+    /// A plain move from `id`.
+    fn pkt(id: &str, x: f32, y: f32) -> Packet {
+        Packet {
+            id: id.into(),
+            color: "#fff".into(),
+            x,
+            y,
+            q: None,
+            rainbow: false,
+            pointer: None,
+            lifted: false,
+            extra: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn extra_fields_are_cut_to_the_policy() {
+        let extra = || {
+            HashMap::from([
+                ("emoji".to_string(), serde_json::json!("🦀")),
+                ("blob".to_string(), serde_json::json!("x".repeat(1_000))),
+            ])
+        };
+
+        let mut e = extra();
+        assert!(ExtraPolicy::parse("strip").unwrap().apply(&mut e));
+        assert!(e.is_empty());
+
+        let mut e = extra();
+        let allow = ExtraPolicy::parse("allow: emoji, trail").unwrap();
+        assert!(allow.apply(&mut e));
+        assert_eq!(e.keys().collect::<Vec<_>>(), ["emoji"]);
+        assert!(!allow.apply(&mut e));
+
+        let mut e = extra();
+        assert!(ExtraPolicy::default().apply(&mut e));
+        assert!(e.is_empty());
+        let mut small = HashMap::from([("emoji".to_string(), serde_json::json!("🦀"))]);
+        assert!(!ExtraPolicy::default().apply(&mut small));
+        assert_eq!(small.len(), 1);
+
+        assert_eq!(ExtraPolicy::parse("nonsense"), None);
+    }
+
+    #[test]
+    fn send_failures_are_told_apart() {
+        let ws = |e| axum::Error::new(e);
+        assert_eq!(
+            send_failure(&ws(tungstenite::Error::ConnectionClosed)),
+            "closed"
+        );
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert_eq!(send_failure(&ws(tungstenite::Error::Io(reset))), "reset");
+        let timeout = std::io::Error::from(std::io::ErrorKind::TimedOut);
+        assert_eq!(send_failure(&ws(tungstenite::Error::Io(timeout))), "io");
+        assert_eq!(send_failure(&axum::Error::new(std::fmt::Error)), "other");
+    }
+
+    #[test]
+    fn ids_are_unique_among_live_sessions() {
+        let ids = Ids::default();
+        let claimed: HashSet<_> = (0..100_000).map(|_| ids.reserve(new_id)).collect();
+        assert_eq!(claimed.len(), 100_000);
+        assert!(claimed
+            .iter()
+            .all(|id| id.len() == ID_LEN && id.bytes().all(|b| b.is_ascii_alphanumeric())));
+    }
+
+    #[test]
+    fn colliding_ids_are_regenerated() {
+        let ids = Ids::default();
+        let mut fixed = ["a", "a", "a", "b"].into_iter().map(String::from);
+        assert_eq!(ids.reserve(|| fixed.next().unwrap()), "a");
+        assert_eq!(ids.reserve(|| fixed.next().unwrap()), "b");
+
+        // released ids may be handed out again
+        ids.release("a");
+        assert_eq!(ids.reserve(|| "a".into()), "a");
+    }
+
+    #[test]
+    fn kicks_reach_the_named_session_only() {
+        let ids = Ids::default();
+        let a = ids.reserve(|| "a".into());
+        let b = ids.reserve(|| "b".into());
+        let mut kicked_a = ids.kicked(&a);
+        let mut kicked_b = ids.kicked(&b);
+
+        assert!(ids.kick(&a));
+        assert!(kicked_a.try_recv().is_ok());
+        assert!(kicked_b.try_recv().is_err());
+        assert!(!ids.kick("nobody"));
+    }
+
+    #[test]
+    fn per_ip_cap_frees_slots_on_drop() {
+        let per_ip = Arc::new(PerIp::default());
+        let ip: IpAddr = [10, 0, 0, 1].into();
+
+        let first = per_ip.acquire(ip, 2).unwrap();
+        let _second = per_ip.acquire(ip, 2).unwrap();
+        assert!(per_ip.acquire(ip, 2).is_none());
+        assert!(per_ip.acquire([10, 0, 0, 2].into(), 2).is_some());
+
+        drop(first);
+        assert!(per_ip.acquire(ip, 2).is_some());
+    }
+
+    #[test]
+    fn seats_are_taken_before_the_upgrade_and_freed_on_drop() {
+        let hub = Hub::new(16);
+        let first = hub.seat(2).unwrap();
+        let _second = hub.seat(2).unwrap();
+        assert!(hub.seat(2).is_none());
+        assert_eq!(hub.online.load(Ordering::Relaxed), 2);
+
+        drop(first);
+        assert!(hub.seat(2).is_some());
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn room_settings_come_from_the_creator_and_are_bounded() {
+        let rooms = Rooms::default();
+        let defaults = RoomSettings {
+            persist: true,
+            ..settings()
+        };
+
+        let asked = params(&[("max_peers", "0"), ("tick", "100000"), ("persist", "0")]);
+        let created = rooms.join("r", defaults, &asked, false);
+        assert_eq!(
+            created.settings,
+            RoomSettings {
+                max_peers: 1,
+                tick_rate: RoomSettings::MAX_TICK_RATE,
+                persist: false,
+            }
+        );
+
+        // later joiners inherit them, unless they moderate
+        let asked = params(&[("max_peers", "8")]);
+        assert_eq!(
+            rooms.join("r", defaults, &asked, false).settings,
+            created.settings
+        );
+        let moderated = rooms.join("r", defaults, &asked, true).settings;
+        assert_eq!(moderated.max_peers, 8);
+        assert!(!moderated.persist);
+
+        assert_eq!(
+            rooms.join("other", defaults, &params(&[]), false).settings,
+            defaults
+        );
+    }
+
+    #[test]
+    fn only_hex_colors_are_pinned() {
+        assert!(is_hex_color("#fff") && is_hex_color("#FFAACC"));
+        for bad in ["fff", "#ffff", "#ggg", "red", "#fff;background:url(x)", ""] {
+            assert!(!is_hex_color(bad), "{bad}");
+        }
+    }
+
+    #[test]
+    fn injected_lines_are_checked_and_timed() {
+        let body = [
+            r##"{"t":5000,"kind":"move","id":"a","color":"#fff","x":0.1,"y":0.2}"##,
+            r##"{"kind":"chat","id":"a","color":"#fff","text":"hi"}"##,
+            "",
+            r##"{"kind":"ping"}"##,
+            "not json",
+            r##"{"t":4000,"kind":"move","id":"b","color":"#fff","x":0.3,"y":0.4}"##,
+            r##"{"t":5250.5,"kind":"move","id":"a","color":"#fff","x":0.2,"y":0.2}"##,
+            r##"{"t":9999999,"kind":"move","id":"a","color":"#fff","x":0.2,"y":0.2}"##,
+            r##"{"kind":"mark","id":"a","color":"red;background:url(x)","x":0.2,"y":0.2}"##,
+        ]
+        .join("\n");
+        let (lines, rejected) = parse_injected(&body, &ExtraPolicy::Strip);
+        // control messages, garbage, too late and not a color
+        assert_eq!(rejected, [4, 5, 8, 9]);
+        // played in time order, from the earliest; the chat follows its move
+        let at: Vec<_> = lines.iter().map(|(at, _)| at.as_micros()).collect();
+        assert_eq!(at, [0, 1_000_000, 1_000_000, 1_250_500]);
+        assert!(matches!(&lines[0].1, Msg::Move(p) if p.id == "b"));
+        assert!(matches!(lines[2].1, Msg::Chat(_)));
+    }
+
+    #[test]
+    fn resume_tokens_work_once_and_only_in_their_room() {
+        let resumes = Resumes::default();
+        let parked = |at| Parked {
+            id: "abc123".into(),
+            color: Some("#ffaacc".into()),
+            room: "jam".into(),
+            at,
+            revoked: false,
+        };
+        resumes.park("t".into(), parked(Instant::now()));
+        assert!(resumes.take("t", "main").is_none());
+        assert!(resumes.take("nope", "jam").is_none());
+        assert_eq!(resumes.take("t", "jam").unwrap().id, "abc123");
+        assert!(resumes.take("t", "jam").is_none());
+        // nothing left to announce once it's been claimed
+        assert!(resumes.expire("t").is_none());
+
+        // kicked while away: no way back, but still there to announce
+        resumes.park("k".into(), parked(Instant::now()));
+        assert!(resumes.revoke("abc123"));
+        assert!(!resumes.revoke("someone"));
+        assert!(resumes.take("k", "jam").is_none());
+        assert!(resumes.expire("k").is_some());
+
+        let Some(stale) = Instant::now().checked_sub(RESUME_GRACE) else {
+            return;
+        };
+        resumes.park("old".into(), parked(stale));
+        assert!(resumes.take("old", "jam").is_none());
+        assert!(resumes.expire("old").is_some());
+    }
+
+    #[test]
+    fn moves_past_the_allowance_are_refused_per_pointer() {
+        let mut limit = MoveLimit::new(2);
+        let now = Instant::now();
+        let sent: Vec<_> = (0..10).map(|_| limit.allows(None, now)).collect();
+        assert_eq!(
+            sent.iter().filter(|ok| **ok).count(),
+            2 * MOVE_SLACK as usize
+        );
+        assert!(!sent[sent.len() - 1]);
+        // another finger has its own, and the next second starts over
+        assert!(limit.allows(Some(1), now));
+        assert!(limit.allows(None, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn only_moves_go_stale() {
+        let ttl = Some(Duration::from_millis(500));
+        let Some(then) = Instant::now().checked_sub(Duration::from_secs(1)) else {
+            return;
+        };
+        let item = |kind, at| Item {
+            kind,
+            txt: String::new(),
+            at,
+            lossy: kind == Some(Kind::Move),
+            cursor: None,
+        };
+        assert!(item(Some(Kind::Move), then).stale(ttl));
+        assert!(!item(Some(Kind::Move), then).stale(None));
+        assert!(!item(Some(Kind::Move), Instant::now()).stale(ttl));
+        assert!(!item(Some(Kind::Chat), then).stale(ttl));
+        assert!(!item(None, then).stale(ttl));
+    }
+
+    #[tokio::test]
+    async fn lifts_are_never_dropped_as_stale() {
+        let hub = Hub::new(16);
+        let (mut hi, lo) = (hub.hi.subscribe(), hub.lo.subscribe());
+        let lift = Packet {
+            pointer: Some(1),
+            lifted: true,
+            ..pkt("a", 0.5, 0.5)
+        };
+        hub.send(Msg::Move(lift));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let item = hi.recv().await.unwrap();
+        assert_eq!(item.kind, Some(Kind::Move));
+        assert!(!item.stale(Some(Duration::from_millis(1))));
+        assert!(lo.is_empty());
+    }
+
+    #[tokio::test]
+    async fn each_lane_counts_its_own_sequence() {
+        let hub = Hub::new(16);
+        let (mut hi, mut lo) = (hub.hi.subscribe(), hub.lo.subscribe());
+        hub.send(Msg::Move(pkt("a", 0.1, 0.5)));
+        hub.announce(Msg::Presence { online: 1 });
+        hub.send(Msg::Move(pkt("a", 0.2, 0.5)));
+        let stamp = |item: Item| {
+            let stamped = Stamped::decode(&item.txt).unwrap();
+            (stamped.seq, stamped.lossy)
+        };
+        // the presence overtaking both moves leaves no gap in either
+        assert_eq!(stamp(hi.recv().await.unwrap()), (Some(0), false));
+        assert_eq!(stamp(lo.recv().await.unwrap()), (Some(0), true));
+        assert_eq!(stamp(lo.recv().await.unwrap()), (Some(1), true));
+    }
+
+    #[tokio::test]
+    async fn moves_overtaken_by_their_lift_are_skipped() {
+        let hub = Hub::new(16);
+        let (mut hi, mut lo) = (hub.hi.subscribe(), hub.lo.subscribe());
+        let finger = |x, lifted| Packet {
+            pointer: Some(1),
+            lifted,
+            ..pkt("a", x, 0.5)
+        };
+        hub.send(Msg::Move(finger(0.1, false)));
+        hub.send(Msg::Move(pkt("a", 0.2, 0.5)));
+        hub.send(Msg::Move(finger(0.3, true)));
+        hub.send(Msg::Move(finger(0.4, false)));
+
+        // the lift first, as `client`'s send task drains `hi` ahead of `lo`
+        let mut lifts = Lifts::default();
+        assert!(lifts.admits(&hi.recv().await.unwrap()));
+        let queued: Vec<_> = (0..3).map(|_| lo.try_recv().unwrap()).collect();
+        let sent: Vec<_> = queued.iter().map(|item| lifts.admits(item)).collect();
+        // the finger's old move is dropped; the main cursor and the next touch aren't
+        assert_eq!(sent, [false, true, true]);
+    }
+
+    #[tokio::test]
+    async fn lifts_say_which_moves_they_supersede() {
+        let hub = Hub::new(16);
+        let (mut hi, mut lo) = (hub.hi.subscribe(), hub.lo.subscribe());
+        let finger = |lifted| Packet {
+            pointer: Some(1),
+            lifted,
+            ..pkt("a", 0.5, 0.5)
+        };
+        hub.send(Msg::Move(finger(false)));
+        hub.send(Msg::Move(finger(false)));
+        hub.send(Msg::Move(finger(true)));
+        let stamp = |item: Item| Stamped::decode(&item.txt).unwrap();
+
+        assert_eq!(stamp(lo.recv().await.unwrap()).supersedes, None);
+        let lift = stamp(hi.recv().await.unwrap());
+        // both moves before it are numbered below 2, whenever they land
+        assert_eq!((lift.seq, lift.supersedes), (Some(0), Some(2)));
+    }
+
+    #[test]
+    fn stream_lines_are_split_and_bounded() {
+        let mut lines = Lines::default();
+        assert_eq!(
+            lines.push(b"{\"a\":1}\n{\"b\""),
+            Some(vec![r#"{"a":1}"#.into()])
+        );
+        assert_eq!(
+            lines.push(b":2}\n\n"),
+            Some(vec![r#"{"b":2}"#.into(), "".into()])
+        );
+        assert_eq!(lines.push(&[b'x'; Lines::MAX]), Some(vec![]));
+        assert_eq!(lines.push(b"x"), None);
+    }
+
+    #[test]
+    fn room_names_are_checked() {
+        assert_eq!(room_name(&params(&[])), Some(DEFAULT_ROOM));
+        assert_eq!(room_name(&params(&[("room", "jam-2")])), Some("jam-2"));
+        assert_eq!(room_name(&params(&[("room", "")])), None);
+        assert_eq!(room_name(&params(&[("room", "a/b")])), None);
+        assert_eq!(room_name(&params(&[("room", &"x".repeat(33))])), None);
+    }
+
+    #[test]
+    fn chat_limit_throttles_then_mutes() {
+        let mut limit = ChatLimit::default();
+        let t0 = Instant::now();
+        for _ in 0..CHAT_BURST {
+            assert!(limit.check(t0).is_ok());
+        }
+        for _ in 1..CHAT_STRIKES {
+            assert!(limit.check(t0).is_err());
+        }
+
+        // one strike too many: muted even once the window has passed
+        assert!(limit.check(t0).is_err());
+        assert!(limit.check(t0 + CHAT_WINDOW).is_err());
+        assert!(limit.check(t0 + CHAT_MUTE).is_ok());
+    }
+
+    #[test]
+    fn floods_pause_longer_each_time_until_forgiven() {
+        let mut flood = Flood::default();
+        let t0 = Instant::now();
+        let burst = |flood: &mut Flood, at: Instant| {
+            (0..FLOOD_STRIKES)
+                .filter_map(|i| flood.violated(at + Duration::from_millis(i as u64)))
+                .last()
+        };
+
+        // occasional hits never add up
+        for i in 0..30 {
+            assert_eq!(flood.violated(t0 + FLOOD_WINDOW * i), None);
+        }
+
+        let t1 = t0 + FLOOD_WINDOW * 30;
+        assert_eq!(burst(&mut flood, t1), Some(FLOOD_PAUSE));
+        assert_eq!(
+            burst(&mut flood, t1 + Duration::from_secs(2)),
+            Some(FLOOD_PAUSE * 2)
+        );
+        for i in 2..10 {
+            let at = t1 + Duration::from_secs(2 * i);
+            assert!(burst(&mut flood, at).unwrap() <= FLOOD_MAX_PAUSE);
+        }
+        assert_eq!(flood.level, 10);
+
+        // behaving for the cooldown starts it over
+        let later = t1 + Duration::from_secs(20) + FLOOD_COOLDOWN;
+        assert_eq!(burst(&mut flood, later), Some(FLOOD_PAUSE));
+    }
+
+    #[test]
+    fn chat_allowance_refills_after_the_window() {
+        let mut limit = ChatLimit::default();
+        let t0 = Instant::now();
+        for _ in 0..CHAT_BURST {
+            assert!(limit.check(t0).is_ok());
+        }
+        assert!(limit.check(t0).is_err());
+        assert!(limit.check(t0 + CHAT_WINDOW).is_ok());
+    }
+
+    #[test]
+    fn slow_clients_are_counted_until_they_close() {
+        let metrics = Arc::new(Metrics::default());
+        let mut lags = Lags {
+            metrics: metrics.clone(),
+            id: "a".into(),
+            count: 0,
+        };
+        for _ in 0..SLOW_LAGS + 2 {
+            lags.lagged(4, 0);
+        }
+        assert_eq!(metrics.slow_clients.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.skipped.load(Ordering::Relaxed), 4 * (SLOW_LAGS + 2));
+
+        drop(lags);
+        assert_eq!(metrics.slow_clients.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn wall_thins_out_busy_rooms() {
+        let rooms = Rooms::default();
+        let defaults = settings();
+        let room = rooms.join("busy", defaults, &params(&[]), false);
+        for i in 0..100 {
+            let color = "#fff".to_string();
+            let cursor = Cursor {
+                color,
+                x: 0.5,
+                y: 0.5,
+            };
+            room.cursors.lock().unwrap().insert(i.to_string(), cursor);
+        }
+        let summary = room.summary();
+        assert_eq!(summary.room, "busy");
+        assert!(summary.cursors.len() <= Room::WALL_CURSORS);
+        assert!(summary.cursors.len() >= Room::WALL_CURSORS / 2);
+    }
+
+    #[test]
+    fn snapshots_carry_recent_trails_within_bounds() {
+        let rooms = Rooms::default();
+        let defaults = settings();
+        let room = rooms.join("main", defaults, &params(&[]), false);
+        let cursor = Cursor {
+            color: "#fff".into(),
+            x: 0.9,
+            y: 0.5,
+        };
+        room.cursors.lock().unwrap().insert("a".into(), cursor);
+        let now = Instant::now();
+        let trail = (0..10).map(|i| {
+            (
+                i as f32 / 10.0,
+                0.5,
+                now - Duration::from_millis(900 - i * 100),
+            )
+        });
+        room.trails
+            .lock()
+            .unwrap()
+            .insert("a".into(), trail.collect());
+
+        let snap = room.snapshot(4, Duration::from_secs(1));
+        assert_eq!(snap.len(), 1);
+        assert_eq!((snap[0].id.as_str(), snap[0].x), ("a", 0.9));
+        let xs: Vec<_> = snap[0].trail.iter().map(|p| p.0).collect();
+        assert_eq!(xs, [0.6, 0.7, 0.8, 0.9]);
+        assert!(snap[0].trail.windows(2).all(|w| w[0].2 >= w[1].2));
+
+        // stale points are left out
+        let snap = room.snapshot(16, Duration::from_millis(450));
+        assert_eq!(snap[0].trail.len(), 5);
+    }
+
+    #[test]
+    fn only_rooms_nobody_holds_are_reaped() {
+        let rooms = Rooms::default();
+        let defaults = settings();
+        let held = rooms.join("held", defaults, &params(&[]), false);
+        drop(rooms.join("empty", defaults, &params(&[]), false));
+
+        assert!(rooms.reap(Duration::from_secs(60)).is_empty());
+        assert_eq!(rooms.reap(Duration::ZERO), ["empty"]);
+        assert!(rooms.map.lock().unwrap().contains_key("held"));
+
+        drop(held);
+        assert_eq!(rooms.reap(Duration::ZERO), ["held"]);
+    }
+
+    #[tokio::test]
+    async fn flush_relays_the_latest_move_per_sender_until_the_room_goes() {
+        let hub = Hub::new(16);
+        let mut rx = hub.lo.subscribe();
+        let moves = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        for x in [0.25, 0.5, 0.75] {
+            moves.lock().unwrap().insert("a".into(), pkt("a", x, 0.0));
+        }
+        let metrics = Arc::new(Metrics::default());
+        let flusher = tokio::spawn(flush_moves(
+            hub.clone(),
+            moves.clone(),
+            Arc::new(AtomicU32::new(100)),
+            metrics.clone(),
+        ));
+
+        let item = rx.recv().await.unwrap();
+        assert!(item.txt.contains("0.75"));
+        assert_eq!(metrics.moves_relayed.load(Ordering::Relaxed), 1);
+
+        drop(moves);
+        tokio::time::timeout(Duration::from_secs(1), flusher)
+            .await
+            .expect("flusher should stop once the room is gone")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_follows_a_moderator_changing_the_tick_rate() {
+        let hub = Hub::new(16);
+        let mut rx = hub.lo.subscribe();
+        let moves = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let tick_rate = Arc::new(AtomicU32::new(100));
+        let metrics = Arc::new(Metrics::default());
+        let flusher = tokio::spawn(flush_moves(
+            hub.clone(),
+            moves.clone(),
+            tick_rate.clone(),
+            metrics,
+        ));
+        moves
+            .lock()
+            .unwrap()
+            .insert("a".into(), pkt("a", 0.25, 0.0));
+        rx.recv().await.unwrap();
+
+        tick_rate.store(2, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        moves.lock().unwrap().insert("a".into(), pkt("a", 0.5, 0.0));
+        let early = tokio::time::timeout(Duration::from_millis(200), rx.recv()).await;
+        assert!(early.is_err(), "relayed at the old rate");
+        let item = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .expect("relayed at the new rate")
+            .unwrap();
+        assert!(item.txt.contains("0.5"));
+
+        drop(moves);
+        tokio::time::timeout(Duration::from_secs(1), flusher)
+            .await
+            .expect("flusher should stop once the room is gone")
+            .unwrap();
+    }
+}
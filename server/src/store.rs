@@ -0,0 +1,156 @@
+//! Stroke persistence. Strokes are queued from the relay path and written in
+//! batches on a blocking thread, so a slow disk never holds up the fan-out.
+
+use rusqlite::{params, Connection};
+use shared::Stroke;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS strokes (
+        id     INTEGER PRIMARY KEY,
+        room   TEXT NOT NULL,
+        stroke TEXT NOT NULL -- shared::Stroke as json
+    );
+    CREATE INDEX IF NOT EXISTS strokes_by_room ON strokes (room, id);
+";
+
+/// Most strokes written in one transaction.
+const BATCH: usize = 256;
+
+/// Handle to the stroke database, shared by every connection.
+#[derive(Clone)]
+pub struct Store {
+    db: Arc<Mutex<Connection>>,
+    queue: mpsc::UnboundedSender<(String, Stroke)>,
+}
+
+impl Store {
+    /// Open (or create) the database at `path`, in memory when `None`, and
+    /// start the background writer. Panics outside a Tokio runtime.
+    pub fn open(path: Option<&str>) -> rusqlite::Result<Self> {
+        let db = Arc::new(Mutex::new(connect(path)?));
+        let (queue, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_batches(db.clone(), rx));
+        Ok(Self { db, queue })
+    }
+
+    /// Queue a stroke drawn in `room` for writing.
+    pub fn save(&self, room: &str, stroke: Stroke) {
+        let _ = self.queue.send((room.to_string(), stroke));
+    }
+
+    /// Everything drawn in `room`, oldest first.
+    pub async fn history(&self, room: &str) -> Vec<Stroke> {
+        let (db, room) = (self.db.clone(), room.to_string());
+        let loaded = tokio::task::spawn_blocking(move || load(&db.lock().unwrap(), &room)).await;
+        match loaded {
+            Ok(Ok(strokes)) => strokes,
+            Ok(Err(e)) => {
+                tracing::warn!(%e, "failed to load strokes");
+                Vec::new()
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+fn connect(path: Option<&str>) -> rusqlite::Result<Connection> {
+    let conn = match path {
+        Some(path) => Connection::open(path)?,
+        None => Connection::open_in_memory()?,
+    };
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+async fn write_batches(
+    db: Arc<Mutex<Connection>>,
+    mut rx: mpsc::UnboundedReceiver<(String, Stroke)>,
+) {
+    let mut batch = Vec::new();
+    while rx.recv_many(&mut batch, BATCH).await > 0 {
+        let rows = std::mem::take(&mut batch);
+        let db = db.clone();
+        let written =
+            tokio::task::spawn_blocking(move || insert(&mut db.lock().unwrap(), &rows)).await;
+        if let Ok(Err(e)) = written {
+            tracing::warn!(%e, "failed to persist strokes");
+        }
+    }
+}
+
+fn insert(conn: &mut Connection, rows: &[(String, Stroke)]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached("INSERT INTO strokes (room, stroke) VALUES (?1, ?2)")?;
+        for (room, stroke) in rows {
+            match serde_json::to_string(stroke) {
+                Ok(json) => stmt.execute(params![room, json])?,
+                Err(e) => {
+                    tracing::warn!(%e, "not persisting unserializable stroke");
+                    continue;
+                }
+            };
+        }
+    }
+    tx.commit()
+}
+
+fn load(conn: &Connection, room: &str) -> rusqlite::Result<Vec<Stroke>> {
+    let mut stmt = conn.prepare_cached("SELECT stroke FROM strokes WHERE room = ?1 ORDER BY id")?;
+    let rows = stmt.query_map([room], |row| row.get::<_, String>(0))?;
+
+    let mut strokes = Vec::new();
+    for json in rows {
+        // skip rows an older/newer build wrote in a shape we can't read
+        if let Ok(stroke) = serde_json::from_str(&json?) {
+            strokes.push(stroke);
+        }
+    }
+    Ok(strokes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(color: &str) -> Stroke {
+        Stroke {
+            id: "abc123".into(),
+            color: color.into(),
+            points: vec![(0.0, 0.0), (0.5, 0.25)],
+        }
+    }
+
+    #[test]
+    fn strokes_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("femtanyl-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        {
+            let mut conn = connect(Some(path)).unwrap();
+            let rows = [
+                ("main".into(), stroke("#f00")),
+                ("other".into(), stroke("#0f0")),
+            ];
+            insert(&mut conn, &rows).unwrap();
+        }
+
+        let conn = connect(Some(path)).unwrap();
+        let strokes = load(&conn, "main").unwrap();
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].color, "#f00");
+        assert_eq!(strokes[0].points, [(0.0, 0.0), (0.5, 0.25)]);
+
+        drop(conn);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn in_memory_starts_empty() {
+        let conn = connect(None).unwrap();
+        assert!(load(&conn, "main").unwrap().is_empty());
+    }
+}
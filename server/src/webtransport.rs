@@ -0,0 +1,177 @@
+//! WebTransport (HTTP/3) sessions, served next to `/ws` when
+//! `Config::webtransport` is set. They carry the same `shared::Msg` json as
+//! the websocket, a line each on one bidirectional stream the client opens,
+//! except that moves the next one supersedes go out as datagrams, since
+//! losing one costs nothing. Past the checks on connect a session is just
+//! another `client`, so the relay doesn't know which transport it's on.
+
+use crate::{admit, close, encode, serve, App, Outgoing, Refusal, CLOSE_GRACE};
+use axum::extract::{ws::Message, Query};
+use axum::http::{StatusCode, Uri};
+use futures_util::{sink, stream, Sink, SinkExt, Stream};
+use shared::{Lines, Msg};
+use std::collections::{HashMap, VecDeque};
+use tokio::time::Duration;
+use wtransport::endpoint::IncomingSession;
+use wtransport::error::{ConnectionError, SendDatagramError};
+use wtransport::{Connection, Endpoint, Identity, RecvStream, SendStream, ServerConfig, VarInt};
+
+/// Path sessions are asked for on, with the same query as `/ws`.
+const PATH: &str = "/wt";
+
+/// How long a client gets to open its stream once the session is up.
+const OPEN_WAIT: Duration = Duration::from_secs(10);
+
+/// Serve sessions as `Config::webtransport` says, if it's set. Only returns
+/// then if the endpoint can't be set up, e.g. over a missing certificate.
+pub(crate) async fn listen(app: App) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(config) = app.config.webtransport.as_ref() else {
+        return Ok(());
+    };
+    let identity = Identity::load_pemfiles(&config.cert, &config.key).await?;
+    let server = ServerConfig::builder()
+        .with_bind_address(config.addr)
+        .with_identity(identity)
+        .build();
+    let endpoint = Endpoint::server(server)?;
+    tracing::info!(addr = %config.addr, "serving webtransport");
+    loop {
+        let pending = endpoint.accept().await;
+        tokio::spawn(session(app.clone(), pending));
+    }
+}
+
+/// Vet a session the way `/ws` vets an upgrade, then serve it.
+async fn session(app: App, pending: IncomingSession) {
+    let Ok(request) = pending.await else { return };
+    // no proxy stands in front of udp, and any forwarding header is the client's own
+    let ip = request.remote_address().ip().to_canonical();
+    let uri = request.path().parse::<Uri>().ok();
+    let Some(uri) = uri.filter(|uri| uri.path() == PATH) else {
+        return request.not_found().await;
+    };
+    let params = Query::<HashMap<String, String>>::try_from_uri(&uri)
+        .map(|query| query.0)
+        .unwrap_or_default();
+    let admitted = match admit(&app, ip, &params) {
+        Ok(admitted) => Ok(admitted),
+        Err(Refusal::Close(reason)) => Err(reason),
+        Err(Refusal::Status(StatusCode::FORBIDDEN)) => return request.forbidden().await,
+        Err(Refusal::Status(StatusCode::TOO_MANY_REQUESTS)) => {
+            return request.too_many_requests().await
+        }
+        // the only other answer there is, for a bad room name too
+        Err(Refusal::Status(_)) => return request.not_found().await,
+    };
+
+    let Ok(connection) = request.accept().await else {
+        return;
+    };
+    let Ok(Ok((send, recv))) = tokio::time::timeout(OPEN_WAIT, connection.accept_bi()).await else {
+        return;
+    };
+    let mut sender = outgoing(connection.clone(), send);
+    match admitted {
+        Ok(admitted) => serve(app, admitted, sender, incoming(connection, recv)).await,
+        Err(reason) => {
+            let _ = sender.send(close(reason).into()).await;
+        }
+    }
+}
+
+/// What `client` writes, as the session carries it.
+fn outgoing(
+    connection: Connection,
+    send: SendStream,
+) -> impl Sink<Outgoing, Error = axum::Error> + Send + Unpin {
+    Box::pin(sink::unfold(
+        (connection, send),
+        |(connection, mut send), out: Outgoing| async move {
+            write(&connection, &mut send, out).await?;
+            Ok((connection, send))
+        },
+    ))
+}
+
+/// Send a lossy move as a datagram if it fits, anything else as a line on
+/// the stream. A close goes as a last line, since browsers don't get to see
+/// the code a session is closed with; the client hangs up on reading it.
+async fn write(
+    connection: &Connection,
+    send: &mut SendStream,
+    out: Outgoing,
+) -> Result<(), axum::Error> {
+    let txt = match out.msg {
+        Message::Text(txt) => txt,
+        Message::Close(frame) => {
+            let (code, reason) = frame.map_or((1000, String::new()), |f| (f.code, f.reason.into()));
+            if let Some(line) = encode(&Msg::Close { code, reason }) {
+                send.write_all(format!("{line}\n").as_bytes())
+                    .await
+                    .map_err(axum::Error::new)?;
+            }
+            let _ = send.finish().await;
+            let _ = tokio::time::timeout(CLOSE_GRACE / 2, connection.closed()).await;
+            connection.close(VarInt::from_u32(code.into()), b"");
+            return Ok(());
+        }
+        // pings and binary: nothing `client` sends
+        _ => return Ok(()),
+    };
+    if out.lossy {
+        match connection.send_datagram(&txt) {
+            Ok(()) => return Ok(()),
+            // the stream takes it instead
+            Err(SendDatagramError::TooLarge | SendDatagramError::UnsupportedByPeer) => {}
+            Err(err) => return Err(axum::Error::new(err)),
+        }
+    }
+    send.write_all(format!("{txt}\n").as_bytes())
+        .await
+        .map_err(axum::Error::new)
+}
+
+/// What the client sends, as `client` reads it: a message a line, ended by
+/// a close if it hung up, or an error if the session was lost.
+fn incoming(
+    connection: Connection,
+    recv: RecvStream,
+) -> impl Stream<Item = Result<Message, axum::Error>> + Send + Unpin {
+    let state = Some((connection, recv, Lines::default(), VecDeque::new()));
+    Box::pin(stream::unfold(state, |state| async move {
+        let (connection, mut recv, mut lines, mut ready) = state?;
+        loop {
+            if let Some(line) = ready.pop_front() {
+                let state = Some((connection, recv, lines, ready));
+                return Some((Ok(Message::Text(line)), state));
+            }
+            let mut buf = [0; 4096];
+            let read = match recv.read(&mut buf).await {
+                Ok(Some(n)) => lines.push(&buf[..n]),
+                // it finished its side, which is leaving like a close frame is
+                Ok(None) => return Some((Ok(Message::Close(None)), None)),
+                Err(err) => return Some((ended(&connection, err).await, None)),
+            };
+            match read {
+                // an empty line is how the client opens its stream
+                Some(got) => ready.extend(got.into_iter().filter(|line| !line.is_empty())),
+                None => {
+                    let err = std::io::Error::new(std::io::ErrorKind::InvalidData, "line too long");
+                    return Some((Err(axum::Error::new(err)), None));
+                }
+            }
+        }
+    }))
+}
+
+/// How a session whose stream failed ended: a close if the client closed
+/// it, rather than it being lost.
+async fn ended(
+    connection: &Connection,
+    err: impl Into<axum::BoxError>,
+) -> Result<Message, axum::Error> {
+    match tokio::time::timeout(CLOSE_GRACE / 2, connection.closed()).await {
+        Ok(ConnectionError::ApplicationClosed(_)) => Ok(Message::Close(None)),
+        _ => Err(axum::Error::new(err)),
+    }
+}
@@ -0,0 +1,355 @@
+//! Connection lifecycle, end to end over real sockets.
+
+use futures_util::{SinkExt, StreamExt};
+use server::{Config, RoomSettings, WebTransport};
+use shared::{CloseReason, Kind, Lines, Msg, Packet, Stamped, Welcome};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+use wtransport::{ClientConfig, Endpoint, Identity};
+
+const IDLE: Duration = Duration::from_millis(300);
+
+fn config() -> Config {
+    Config {
+        port: 0,
+        admin_token: None,
+        trust_proxy: false,
+        db_path: None,
+        max_per_ip: 16,
+        max_speed: None,
+        room: RoomSettings {
+            max_peers: 16,
+            persist: false,
+            ..RoomSettings::default()
+        },
+        room_grace: Duration::from_secs(300),
+        idle_timeout: IDLE,
+        coalesce: true,
+        extra: Default::default(),
+        snapshot_trail: 16,
+        stale_after: None,
+        webtransport: None,
+        tunables: Default::default(),
+    }
+}
+
+/// A plain move; the server fills in who it's from.
+fn pkt(x: f32, y: f32) -> Packet {
+    Packet {
+        id: String::new(),
+        color: "#ffaacc".into(),
+        x,
+        y,
+        q: None,
+        rainbow: false,
+        pointer: None,
+        lifted: false,
+        extra: Default::default(),
+    }
+}
+
+/// Serve a fresh router on a loopback port.
+fn serve(config: Config) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = server::router(config).unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>()),
+    );
+    addr
+}
+
+type Ws =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Connect and wait for the welcome, returning the session id it names.
+async fn join(addr: SocketAddr) -> (Ws, String) {
+    let (ws, welcome) = welcomed(addr, "").await;
+    (ws, welcome.id.unwrap())
+}
+
+/// Connect with `query` on the url and wait for the welcome.
+async fn welcomed(addr: SocketAddr, query: &str) -> (Ws, Welcome) {
+    let url = format!("ws://{addr}/ws?v={}{query}", shared::VERSION);
+    let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+    while let Some(Ok(Message::Text(txt))) = ws.next().await {
+        if let Some(Msg::Welcome(welcome)) = Msg::decode(&txt) {
+            return (ws, welcome);
+        }
+    }
+    panic!("closed before the welcome");
+}
+
+/// Everything `ws` is sent within `wait`.
+async fn drain(ws: &mut Ws, wait: Duration) -> Vec<Msg> {
+    let mut got = Vec::new();
+    while let Ok(Some(Ok(Message::Text(txt)))) = tokio::time::timeout(wait, ws.next()).await {
+        got.extend(Stamped::decode(&txt).map(|s| s.msg));
+    }
+    got
+}
+
+// Real sockets rule out `tokio::time::pause`: with the clock auto-advancing
+// whenever the runtime waits on I/O, the timeout would fire mid-handshake.
+// A short timeout keeps it quick instead.
+#[tokio::test]
+async fn silent_clients_are_dropped_and_peers_told() {
+    let addr = serve(config());
+    let started = Instant::now();
+    let (mut quiet, quiet_id) = join(addr).await;
+    let (mut chatty, _) = join(addr).await;
+
+    // the quiet one only listens, and is hung up on with a reason
+    let closed = tokio::spawn(async move {
+        while let Some(Ok(msg)) = quiet.next().await {
+            if let Message::Close(frame) = msg {
+                return frame.map(|f| f.code);
+            }
+        }
+        None
+    });
+
+    // the other keeps itself alive until it hears the quiet one went
+    let keepalive = serde_json::to_string(&Msg::Subscribe {
+        kinds: Kind::ALL.to_vec(),
+    })
+    .unwrap();
+    let left = loop {
+        assert!(started.elapsed() < IDLE * 10, "no leave broadcast");
+        chatty.send(Message::Text(keepalive.clone())).await.unwrap();
+        let Ok(Some(Ok(Message::Text(txt)))) = tokio::time::timeout(IDLE / 4, chatty.next()).await
+        else {
+            continue;
+        };
+        if let Some(Stamped {
+            msg: Msg::Leave { id },
+            ..
+        }) = Stamped::decode(&txt)
+        {
+            break id;
+        }
+    };
+    assert_eq!(left, quiet_id);
+    // not before it had been silent for the whole timeout, nor long after
+    let waited = started.elapsed();
+    assert!(waited >= IDLE && waited < IDLE * 3, "left after {waited:?}");
+
+    let code = tokio::time::timeout(IDLE, closed).await.unwrap().unwrap();
+    assert_eq!(code, Some(CloseCode::from(CloseReason::Timeout as u16)));
+}
+
+#[tokio::test]
+async fn dropped_clients_resume_as_themselves() {
+    // nobody here should time out
+    let addr = serve(Config {
+        idle_timeout: Duration::from_secs(60),
+        ..config()
+    });
+    let (mut peer, _) = join(addr).await;
+    let (ws, first) = welcomed(addr, "&color=%23ffaacc").await;
+    let (id, token) = (first.id.unwrap(), first.resume.unwrap());
+
+    // gone without a close frame, like a lost network
+    drop(ws);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (ws, again) = welcomed(addr, &format!("&resume={token}")).await;
+    assert_eq!(again.id.as_deref(), Some(id.as_str()));
+    assert_eq!(again.color.as_deref(), Some("#ffaacc"));
+    // a fresh token each time; the old one is spent
+    assert!(again.resume.is_some_and(|t| t != token));
+    let (_, other) = welcomed(addr, &format!("&resume={token}")).await;
+    assert_ne!(other.id, Some(id.clone()));
+
+    let seen = drain(&mut peer, Duration::from_millis(200)).await;
+    assert!(
+        !seen.iter().any(|m| matches!(m, Msg::Leave { .. })),
+        "{seen:?}"
+    );
+
+    // leaving properly is announced straight away
+    let mut ws = ws;
+    ws.close(None).await.unwrap();
+    let seen = drain(&mut peer, Duration::from_millis(200)).await;
+    assert!(
+        seen.iter()
+            .any(|m| matches!(m, Msg::Leave { id: left } if *left == id)),
+        "{seen:?}"
+    );
+}
+
+#[tokio::test]
+async fn extra_pointers_are_cursors_of_their_own() {
+    let addr = serve(Config {
+        idle_timeout: Duration::from_secs(60),
+        ..config()
+    });
+    let (mut peer, _) = join(addr).await;
+    let (mut ws, id) = join(addr).await;
+    let finger = |pointer, lifted| {
+        let pkt = Packet {
+            pointer: Some(pointer),
+            lifted,
+            ..pkt(0.25, 0.5)
+        };
+        Message::Text(serde_json::to_string(&Msg::Move(pkt)).unwrap())
+    };
+    let key = format!("{id}/1");
+    let snapshot = |seen: Vec<Msg>| {
+        seen.into_iter()
+            .find_map(|m| match m {
+                Msg::Snapshot { peers } => Some(peers.into_iter().map(|p| p.id).collect()),
+                _ => None,
+            })
+            .unwrap_or_else(Vec::new)
+    };
+
+    // past the last sub-id a pointer is dropped rather than relayed
+    ws.send(finger(1, false)).await.unwrap();
+    ws.send(finger(Packet::MAX_POINTERS + 1, false))
+        .await
+        .unwrap();
+    let seen = drain(&mut peer, Duration::from_millis(200)).await;
+    let moves: Vec<_> = seen
+        .iter()
+        .filter_map(|m| match m {
+            Msg::Move(pkt) => Some((pkt.id.as_str(), pkt.pointer)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(moves, [(id.as_str(), Some(1))]);
+    let (mut late, _) = join(addr).await;
+    let seated = snapshot(drain(&mut late, Duration::from_millis(200)).await);
+    assert!(seated.contains(&key), "{seated:?}");
+
+    // lifting it takes the cursor off the board
+    ws.send(finger(1, true)).await.unwrap();
+    let seen = drain(&mut peer, Duration::from_millis(200)).await;
+    assert!(
+        seen.iter()
+            .any(|m| matches!(m, Msg::Move(pkt) if pkt.lifted && pkt.cursor_key() == key)),
+        "{seen:?}"
+    );
+    let (mut later, _) = join(addr).await;
+    let seated = snapshot(drain(&mut later, Duration::from_millis(200)).await);
+    assert!(!seated.contains(&key), "{seated:?}");
+}
+
+#[tokio::test]
+async fn leaving_cleanly_is_announced_even_behind_moves() {
+    let addr = serve(Config {
+        idle_timeout: Duration::from_secs(60),
+        ..config()
+    });
+    let (mut peer, _) = join(addr).await;
+    let (mut ws, id) = join(addr).await;
+    for i in 0..20 {
+        let txt = serde_json::to_string(&Msg::Move(pkt(i as f32 / 20.0, 0.5))).unwrap();
+        ws.send(Message::Text(txt)).await.unwrap();
+    }
+    ws.close(None).await.unwrap();
+
+    // on the lane moves can't crowd out; moves still queued may follow it
+    let seen = drain(&mut peer, Duration::from_millis(200)).await;
+    assert!(
+        seen.iter()
+            .any(|m| matches!(m, Msg::Leave { id: left } if *left == id)),
+        "{seen:?}"
+    );
+}
+
+#[tokio::test]
+async fn webtransport_sessions_meet_websocket_peers() {
+    let identity = Identity::self_signed(["localhost", "127.0.0.1"]).unwrap();
+    let dir = std::env::temp_dir().join(format!("femtanyl-wt-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    let chain = identity.certificate_chain();
+    chain.store_pemfile(&cert).await.unwrap();
+    identity
+        .private_key()
+        .store_secret_pemfile(&key)
+        .await
+        .unwrap();
+    let wt_addr = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let addr = serve(Config {
+        idle_timeout: Duration::from_secs(60),
+        webtransport: Some(WebTransport {
+            addr: wt_addr,
+            cert: cert.to_string_lossy().into(),
+            key: key.to_string_lossy().into(),
+        }),
+        ..config()
+    });
+
+    // the endpoint comes up alongside the router, not before it returns
+    let client = ClientConfig::builder()
+        .with_bind_default()
+        .with_server_certificate_hashes([chain.as_slice()[0].hash()])
+        .build();
+    let client = Endpoint::client(client).unwrap();
+    let url = format!("https://{wt_addr}/wt?v={}", shared::VERSION);
+    let mut tries = 0;
+    let session = loop {
+        match tokio::time::timeout(Duration::from_secs(1), client.connect(&url)).await {
+            Ok(Ok(session)) => break session,
+            _ if tries < 10 => tries += 1,
+            other => panic!("no webtransport session: {other:?}"),
+        }
+    };
+    let (mut send, mut recv) = session.open_bi().await.unwrap().await.unwrap();
+    send.write_all(b"\n").await.unwrap();
+    let mut lines = Lines::default();
+    let mut buf = [0; 4096];
+    let id = 'welcomed: loop {
+        let n = recv
+            .read(&mut buf)
+            .await
+            .unwrap()
+            .expect("closed before the welcome");
+        for line in lines.push(&buf[..n]).unwrap() {
+            if let Some(Msg::Welcome(welcome)) = Msg::decode(&line) {
+                break 'welcomed welcome.id.unwrap();
+            }
+        }
+    };
+
+    // its moves reach the websocket like any other
+    let (mut peer, _) = join(addr).await;
+    let line = serde_json::to_string(&Msg::Move(pkt(0.25, 0.5))).unwrap();
+    send.write_all(format!("{line}\n").as_bytes())
+        .await
+        .unwrap();
+    let seen = drain(&mut peer, Duration::from_millis(200)).await;
+    assert!(
+        seen.iter()
+            .any(|m| matches!(m, Msg::Move(pkt) if pkt.id == id)),
+        "{seen:?}"
+    );
+
+    // and the websocket's come back as datagrams
+    let txt = serde_json::to_string(&Msg::Move(pkt(0.75, 0.5))).unwrap();
+    peer.send(Message::Text(txt)).await.unwrap();
+    // after the echo of its own
+    let relayed = async {
+        loop {
+            let datagram = session.receive_datagram().await.unwrap();
+            let stamped = Stamped::decode(std::str::from_utf8(&datagram).unwrap()).unwrap();
+            match stamped.msg {
+                Msg::Move(pkt) if pkt.id != id => return (stamped.lossy, pkt.pos()),
+                _ => {}
+            }
+        }
+    };
+    let (lossy, (x, _)) = tokio::time::timeout(Duration::from_secs(1), relayed)
+        .await
+        .unwrap();
+    assert!(lossy);
+    assert!((x - 0.75).abs() < 0.01, "{x}");
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -1,12 +1,498 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Wire format version, sent by clients as `/ws?v=..` when they connect.
+/// Bump on changes an older build would misparse.
+pub const VERSION: u8 = 1;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Packet {
     pub id: String,
     pub color: String,
     pub x: f32,
     pub y: f32,
+    /// When set, `x`/`y` are integer steps on a grid of `q` bits per axis.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q: Option<u8>,
+    /// Sender's hue cycles; receivers animate it locally rather than being sent every step.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub rainbow: bool,
+    /// Which of the sender's extra pointers (fingers, a pen) this is, 1 up to
+    /// `MAX_POINTERS`; unset for the main one. Each is a cursor of its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<u8>,
+    /// The pointer has been lifted: this is where it ended, and its cursor goes.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub lifted: bool,
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
 }
+
+impl Packet {
+    pub const MAX_PRECISION: u8 = 16;
+    pub const MAX_POINTERS: u8 = 9;
+
+    /// What the cursor this moves is known by: the sender's id, with the
+    /// pointer after a `/` for extra ones. See [`cursor_owner`].
+    pub fn cursor_key(&self) -> String {
+        match self.pointer {
+            Some(p) => format!("{}/{p}", self.id),
+            None => self.id.clone(),
+        }
+    }
+
+    /// Normalized [0,1] position, undoing any quantization.
+    pub fn pos(&self) -> (f32, f32) {
+        match self.q {
+            Some(bits) => {
+                let steps = grid_steps(bits);
+                (self.x / steps, self.y / steps)
+            }
+            None => (self.x, self.y),
+        }
+    }
+
+    /// Move to a normalized position, keeping any quantization.
+    pub fn set_pos(&mut self, x: f32, y: f32) {
+        let q = self.q.take();
+        (self.x, self.y) = (x, y);
+        if let Some(bits) = q {
+            self.quantize(bits);
+        }
+    }
+
+    /// Snap a normalized position onto a `bits`-per-axis grid.
+    pub fn quantize(&mut self, bits: u8) {
+        let (x, y) = self.pos();
+        let bits = bits.clamp(1, Self::MAX_PRECISION);
+        let steps = grid_steps(bits);
+        self.x = (x.clamp(0.0, 1.0) * steps).round();
+        self.y = (y.clamp(0.0, 1.0) * steps).round();
+        self.q = Some(bits);
+    }
+}
+
+/// Session id a [`Packet::cursor_key`] belongs to.
+pub fn cursor_owner(key: &str) -> &str {
+    key.split_once('/').map_or(key, |(id, _)| id)
+}
+
+/// `#rgb` or `#rrggbb`: the only colors relayed, since peers paint with them.
+pub fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+fn grid_steps(bits: u8) -> f32 {
+    ((1u32 << bits.clamp(1, Packet::MAX_PRECISION)) - 1) as f32
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Chat {
+    pub id: String,
+    pub color: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Stroke {
+    pub id: String,
+    pub color: String,
+    pub points: Vec<(f32, f32)>,
+}
+
+/// A transient "look here" marker dropped with a click. Not stored.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mark {
+    pub id: String,
+    pub color: String,
+    /// normalized [0,1] position
+    pub x: f32,
+    pub y: f32,
+}
+
+/// One room in the kiosk wall overview served at `GET /wall`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoomSummary {
+    pub room: String,
+    pub online: usize,
+    /// last known cursors, thinned out in busy rooms
+    pub cursors: Vec<Cursor>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Cursor {
+    pub color: String,
+    /// normalized [0,1] position
+    pub x: f32,
+    pub y: f32,
+}
+
+/// A cursor already on the board when a connection joins.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PeerState {
+    pub id: String,
+    pub color: String,
+    /// normalized [0,1] position
+    pub x: f32,
+    pub y: f32,
+    /// where it's been lately, oldest first, as (x, y, ms ago)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trail: Vec<(f32, f32, u32)>,
+}
+
+/// Handshake the server sends first on every connection. Everything is
+/// optional so fields can be added without a version bump.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Welcome {
+    /// session id the server assigned; relayed messages carrying it are our own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// color pinned to this connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// live connections in the room, counting this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub online: Option<usize>,
+    /// room joined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    /// most connections the room takes at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_peers: Option<usize>,
+    /// whether the room's strokes are kept across sessions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist: Option<bool>,
+    /// server build, for bug reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// most moves per second the server wants from a client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick_rate: Option<u32>,
+    /// whether a moderator has paused moves in the room
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<bool>,
+    /// reconnect with `/ws?resume=..` after a drop to come back as the same
+    /// session, id and color, without peers seeing a leave
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume: Option<String>,
+}
+
+/// Kinds of relayed messages a connection can subscribe to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Move,
+    Chat,
+    Stroke,
+    Mark,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [Kind::Move, Kind::Chat, Kind::Stroke, Kind::Mark];
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Msg {
+    Move(Packet),
+    Chat(Chat),
+    Stroke(Stroke),
+    Mark(Mark),
+    /// client -> server: only forward these kinds to me
+    Subscribe {
+        kinds: Vec<Kind>,
+    },
+    /// moderator -> server: ban an address or session id
+    Ban {
+        target: String,
+    },
+    /// moderator -> server: disconnect this session (it may come back, unlike a ban)
+    Kick {
+        id: String,
+    },
+    /// moderator -> server: stop (or resume) relaying moves in my room;
+    /// server -> clients: the room's cursors are paused, or moving again
+    Freeze {
+        frozen: bool,
+    },
+    /// client -> server: change the color pinned to my connection (rate limited)
+    SetColor {
+        color: String,
+    },
+    /// client -> server: echo `t` back, for measuring round trips
+    Ping {
+        t: f64,
+    },
+    /// server -> client: reply to a `Ping`
+    Pong {
+        t: f64,
+    },
+    /// server -> client: first message on a connection
+    Welcome(Welcome),
+    /// server -> client: every cursor already on the board (maybe none), right after the welcome
+    Snapshot {
+        peers: Vec<PeerState>,
+    },
+    /// server -> client: something this connection should be told, e.g. that it's throttled
+    Notice {
+        text: String,
+    },
+    /// server -> clients: how many connections are live, sent on join/leave
+    Presence {
+        online: usize,
+    },
+    /// server -> clients: still here, sent every few seconds however quiet the room is
+    Tick,
+    /// server -> clients: this connection closed, forget its cursor
+    Leave {
+        id: String,
+    },
+    /// server -> client, over WebTransport: why the session is ending, the
+    /// last line on its stream (a websocket gets it as the close frame)
+    Close {
+        code: u16,
+        reason: String,
+    },
+}
+
+impl Msg {
+    /// Parse a wire message; untagged text is a legacy bare `Packet`.
+    pub fn decode(txt: &str) -> Option<Msg> {
+        serde_json::from_str::<Msg>(txt)
+            .or_else(|_| serde_json::from_str::<Packet>(txt).map(Msg::Move))
+            .ok()
+    }
+
+    /// Kind used for subscription filtering, `None` for control messages.
+    pub fn kind(&self) -> Option<Kind> {
+        match self {
+            Msg::Move(_) => Some(Kind::Move),
+            Msg::Chat(_) => Some(Kind::Chat),
+            Msg::Stroke(_) => Some(Kind::Stroke),
+            Msg::Mark(_) => Some(Kind::Mark),
+            Msg::Subscribe { .. }
+            | Msg::Ban { .. }
+            | Msg::Kick { .. }
+            | Msg::Freeze { .. }
+            | Msg::SetColor { .. }
+            | Msg::Ping { .. }
+            | Msg::Pong { .. }
+            | Msg::Welcome(_)
+            | Msg::Snapshot { .. }
+            | Msg::Notice { .. }
+            | Msg::Presence { .. }
+            | Msg::Tick
+            | Msg::Leave { .. }
+            | Msg::Close { .. } => None,
+        }
+    }
+
+    /// Sender color of a relayed message.
+    pub fn color(&self) -> Option<&str> {
+        match self {
+            Msg::Move(pkt) => Some(&pkt.color),
+            Msg::Chat(chat) => Some(&chat.color),
+            Msg::Stroke(stroke) => Some(&stroke.color),
+            Msg::Mark(mark) => Some(&mark.color),
+            _ => None,
+        }
+    }
+
+    /// Overwrite the sender color (the server pins one per connection).
+    pub fn set_color(&mut self, color: &str) {
+        match self {
+            Msg::Move(pkt) => pkt.color = color.to_string(),
+            Msg::Chat(chat) => chat.color = color.to_string(),
+            Msg::Stroke(stroke) => stroke.color = color.to_string(),
+            Msg::Mark(mark) => mark.color = color.to_string(),
+            _ => {}
+        }
+    }
+
+    /// Overwrite the sender id (the server is authoritative for ids).
+    pub fn set_id(&mut self, id: &str) {
+        match self {
+            Msg::Move(pkt) => pkt.id = id.to_string(),
+            Msg::Chat(chat) => chat.id = id.to_string(),
+            Msg::Stroke(stroke) => stroke.id = id.to_string(),
+            Msg::Mark(mark) => mark.id = id.to_string(),
+            Msg::Subscribe { .. }
+            | Msg::Ban { .. }
+            | Msg::Kick { .. }
+            | Msg::Freeze { .. }
+            | Msg::SetColor { .. }
+            | Msg::Ping { .. }
+            | Msg::Pong { .. }
+            | Msg::Welcome(_)
+            | Msg::Snapshot { .. }
+            | Msg::Notice { .. }
+            | Msg::Presence { .. }
+            | Msg::Tick
+            | Msg::Leave { .. }
+            | Msg::Close { .. } => {}
+        }
+    }
+}
+
+/// A relayed message as broadcast by the server, stamped with the hub's
+/// sequence number so receivers can spot drops and reordering.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Stamped {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// `seq` counts the moves lane, which everything else may overtake, so
+    /// each lane has its own sequence; unset for the other one
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub lossy: bool,
+    /// on a lift: the moves lane's next `seq` when it was sent, so a move
+    /// numbered below that came from before it, however late it arrives
+    /// (WebTransport datagrams can be overtaken)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supersedes: Option<u64>,
+    #[serde(flatten)]
+    pub msg: Msg,
+}
+
+impl Stamped {
+    /// Parse a broadcast; untagged text is a legacy bare `Packet`.
+    pub fn decode(txt: &str) -> Option<Stamped> {
+        serde_json::from_str::<Stamped>(txt).ok().or_else(|| {
+            let msg = Msg::Move(serde_json::from_str::<Packet>(txt).ok()?);
+            Some(Stamped {
+                seq: None,
+                lossy: false,
+                supersedes: None,
+                msg,
+            })
+        })
+    }
+}
+
+/// Splits a WebTransport stream back into the messages on it, one per line
+/// (serialized json never has a raw newline in it).
+#[derive(Default, Debug)]
+pub struct Lines {
+    buf: Vec<u8>,
+}
+
+impl Lines {
+    /// Longest line taken; a peer sending longer ones is misbehaving.
+    pub const MAX: usize = 64 * 1024;
+
+    /// Add what was read, returning the lines it completes, or `None` once
+    /// the one in progress is longer than `MAX`.
+    pub fn push(&mut self, bytes: &[u8]) -> Option<Vec<String>> {
+        self.buf.extend_from_slice(bytes);
+        let mut lines = Vec::new();
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            lines.push(String::from_utf8_lossy(&line[..end]).into_owned());
+        }
+        (self.buf.len() <= Self::MAX).then_some(lines)
+    }
+}
+
+/// Client tunables an operator can set without rebuilding the wasm, served
+/// at `GET /config`. Missing fields take the defaults below.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Tunables {
+    /// how long trail points live, in ms
+    pub trail_life: f64,
+    /// fraction of the remaining distance a cursor covers per ms
+    pub speed_factor: f64,
+    /// keyboard cursor speed, px per ms
+    pub key_speed: f64,
+    /// gamepad cursor speed at full stick, px per ms
+    pub pad_speed: f64,
+    /// loudest the movement sound gets (gain)
+    pub volume: f64,
+    /// WebTransport endpoint to prefer over the websocket, when the server
+    /// runs one (`https://host:port/wt`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webtransport: Option<String>,
+}
+
+impl Default for Tunables {
+    fn default() -> Self {
+        Self {
+            trail_life: 1_000.0,
+            speed_factor: 0.01,
+            key_speed: 0.5,
+            pad_speed: 0.8,
+            volume: 0.1,
+            webtransport: None,
+        }
+    }
+}
+
+/// Why the server closed a connection, sent as the WebSocket close code.
+/// Codes live in the 4000-4999 range RFC 6455 leaves to applications.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    /// silent for too long
+    Timeout = 4000,
+    /// no room for another participant
+    RoomFull = 4001,
+    /// bad or missing credentials
+    Unauthorized = 4002,
+    /// kept exceeding rate limits
+    RateLimited = 4003,
+    /// banned by a moderator
+    Banned = 4004,
+    /// client speaks a wire format the server no longer understands
+    Outdated = 4005,
+    /// disconnected by a moderator
+    Kicked = 4006,
+}
+
+impl CloseReason {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Some(match code {
+            4000 => CloseReason::Timeout,
+            4001 => CloseReason::RoomFull,
+            4002 => CloseReason::Unauthorized,
+            4003 => CloseReason::RateLimited,
+            4004 => CloseReason::Banned,
+            4005 => CloseReason::Outdated,
+            4006 => CloseReason::Kicked,
+            _ => return None,
+        })
+    }
+
+    /// Human-readable reason, sent alongside the code.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Timeout => "idle timeout",
+            CloseReason::RoomFull => "room is full",
+            CloseReason::Unauthorized => "unauthorized",
+            CloseReason::RateLimited => "rate limited",
+            CloseReason::Banned => "banned",
+            CloseReason::Outdated => "outdated client, please refresh",
+            CloseReason::Kicked => "kicked by a moderator",
+        }
+    }
+
+    /// Whether reconnecting (after a backoff) can be expected to help.
+    pub fn retry(self) -> bool {
+        match self {
+            CloseReason::Timeout | CloseReason::RoomFull | CloseReason::RateLimited => true,
+            // a kick that reconnects by itself wouldn't be much of one
+            CloseReason::Unauthorized
+            | CloseReason::Banned
+            | CloseReason::Outdated
+            | CloseReason::Kicked => false,
+        }
+    }
+}